@@ -33,7 +33,7 @@ where
 
 /// Like run, but rather than doing the only thing possible with plain `nb` and block, use that our
 /// sockets have file descriptors and block at the OS level.
-fn run_with_unix(stack: &mut std_embedded_nal::Stack) -> Result<(), std::io::Error> {
+fn run_with_unix(stack: &mut mm_std_embedded_nal::Stack) -> Result<(), std::io::Error> {
     let target = SocketAddr::new(
         block!(stack.get_host_by_name("localhost", embedded_nal::AddrType::IPv6))?,
         5683,
@@ -76,7 +76,7 @@ fn run_with_unix(stack: &mut std_embedded_nal::Stack) -> Result<(), std::io::Err
 }
 
 fn main() {
-    let mut stack = std_embedded_nal::Stack::default();
+    let mut stack = mm_std_embedded_nal::Stack::default();
 
     #[cfg(unix)]
     run_with_unix(&mut stack).expect("Error running the main program");
@@ -29,7 +29,7 @@ where
 }
 
 fn main() {
-    let mut stack = std_embedded_nal::Stack::default();
+    let mut stack = mm_std_embedded_nal::Stack::default();
 
     run(&mut stack).expect("Error running the main program")
 }
@@ -0,0 +1,118 @@
+//! Support for systemd-style socket activation
+//!
+//! When started by a service manager that supports the [socket activation protocol], the
+//! listening sockets are created by the service manager, and passed to the application as file
+//! descriptors along with the `LISTEN_PID` and `LISTEN_FDS` environment variables. Rather than
+//! binding sockets through the [embedded_nal] traits, the application then takes them using
+//! [`listen_fds()`], and turns them into sockets of this crate using [`udp_socket()`] and
+//! [`tcp_listener()`].
+//!
+//! The helpers for taking and checking the inherited file descriptors are deliberately duplicated
+//! in the `activation` module of the std-embedded-nal-async crate, as the crates share no code; changes
+//! to them apply to both.
+//!
+//! [socket activation protocol]: https://www.freedesktop.org/software/systemd/man/sd_listen_fds.html
+
+use crate::{TcpSocket, UdpSocket};
+use nix::sys::socket::{getsockname, getsockopt, sockopt, AddressFamily, SockType};
+use nix::sys::socket::{SockaddrLike, SockaddrStorage};
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// File descriptor number of the first socket passed in (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// An std::io::Error compatible error type expressing that an inherited socket can not be used
+/// in the requested role
+#[derive(Debug)]
+struct Unsuitable(&'static str);
+
+impl std::fmt::Display for Unsuitable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Inherited socket is unsuitable: {}", self.0)
+    }
+}
+
+impl std::error::Error for Unsuitable {}
+
+fn unsuitable(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, Unsuitable(reason))
+}
+
+/// Take the file descriptors passed in by the service manager.
+///
+/// If the process was not started through socket activation (or the sockets were already taken
+/// in an earlier call), the result is empty.
+///
+/// Like `sd_listen_fds(1)`, this removes the environment variables, so that the file descriptors
+/// are not claimed twice, and are not announced to child processes. For that reason, it should
+/// be called early in the program, before any threads are started that might access the
+/// environment.
+pub fn listen_fds() -> io::Result<Vec<OwnedFd>> {
+    let pid = std::env::var("LISTEN_PID");
+    let fds = std::env::var("LISTEN_FDS");
+
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let (Ok(pid), Ok(fds)) = (pid, fds) else {
+        return Ok(vec![]);
+    };
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(vec![]);
+    }
+    let fds: RawFd = fds.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "LISTEN_FDS is not a number of file descriptors",
+        )
+    })?;
+
+    (LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(fds))
+        .map(|fd| {
+            nix::fcntl::fcntl(
+                fd,
+                nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC),
+            )?;
+            // The service manager passed this file descriptor for our exclusive use, and the
+            // environment variables are gone, so nothing else takes ownership of it.
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        })
+        .collect()
+}
+
+/// Check that the file descriptor is an IP socket of the given type.
+fn check_socket(fd: &OwnedFd, expected: SockType) -> io::Result<()> {
+    if getsockopt(fd, sockopt::SockType).map_err(|_| unsuitable("not a socket"))? != expected {
+        return Err(unsuitable(match expected {
+            SockType::Datagram => "not a datagram socket",
+            _ => "not a stream socket",
+        }));
+    }
+
+    let local: SockaddrStorage = getsockname(fd.as_raw_fd())?;
+    match local.family() {
+        Some(AddressFamily::Inet) | Some(AddressFamily::Inet6) => Ok(()),
+        _ => Err(unsuitable("not an IP socket")),
+    }
+}
+
+/// Turn an inherited file descriptor into a bound (or, if it was connected by the service
+/// manager, connected) UDP socket.
+pub fn udp_socket(fd: OwnedFd) -> io::Result<UdpSocket> {
+    check_socket(&fd, SockType::Datagram)?;
+
+    UdpSocket::try_from(std::net::UdpSocket::from(fd))
+}
+
+/// Turn an inherited file descriptor into a bound TCP socket, on which connections can be
+/// accepted.
+pub fn tcp_listener(fd: OwnedFd) -> io::Result<TcpSocket> {
+    check_socket(&fd, SockType::Stream)?;
+    if !getsockopt(&fd, sockopt::AcceptConn)? {
+        return Err(unsuitable("not listening"));
+    }
+
+    TcpSocket::try_from(std::net::TcpListener::from(fd))
+}
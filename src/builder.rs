@@ -0,0 +1,330 @@
+//! Configuration of a [Stack]
+
+use crate::Stack;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Address families a [Stack] may use
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FamilyPolicy {
+    /// Use IPv4 and IPv6 alike
+    ///
+    /// Sockets bound without a configured bind address are bound to the unspecified IPv6
+    /// address, which (depending on the operating system's setup) accepts IPv4 traffic as well.
+    Any,
+    /// Use IPv4 only
+    ///
+    /// IPv6 remote addresses are rejected, name resolution returns only IPv4 addresses, and
+    /// sockets bound without a configured bind address are bound to the unspecified IPv4 address.
+    Ipv4Only,
+    /// Use IPv6 only
+    ///
+    /// IPv4 remote addresses are rejected, name resolution returns only IPv6 addresses, and bound
+    /// sockets do not accept IPv4 traffic through V4-mapped addresses.
+    Ipv6Only,
+}
+
+/// Which addresses name resolution returns when any family is acceptable
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DnsPreference {
+    /// Return the first address the system resolver produces
+    SystemOrder,
+    /// Return an IPv4 address if there is any
+    PreferIpv4,
+    /// Return an IPv6 address if there is any
+    PreferIpv6,
+}
+
+/// What a socket created by a [Stack] is used for, as reported to its
+/// [socket hook](StackBuilder::socket_hook)
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SocketRole {
+    /// A UDP socket that is about to be bound to a local address
+    UdpBound,
+    /// A UDP socket that is about to be connected to a remote address
+    UdpConnected,
+    /// A TCP socket that is about to listen for connections
+    TcpListener,
+    /// A TCP socket that is about to connect to a remote address
+    TcpConnection,
+}
+
+/// Callback run on every socket a [Stack] creates, see [StackBuilder::socket_hook]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[derive(Clone)]
+pub(crate) struct SocketHook(pub(crate) std::sync::Arc<HookFn>);
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+type HookFn = dyn Fn(std::os::unix::io::BorrowedFd<'_>, SocketRole) -> io::Result<()> + Send + Sync;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl std::fmt::Debug for SocketHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("SocketHook")
+    }
+}
+
+/// Socket options applied to every socket a [Stack] creates
+#[derive(Clone, Debug)]
+pub(crate) struct SocketOptions {
+    pub(crate) reuse_address: bool,
+    pub(crate) ttl: Option<u32>,
+    pub(crate) recv_buffer_size: Option<usize>,
+    pub(crate) send_buffer_size: Option<usize>,
+    pub(crate) tcp_nodelay: Option<bool>,
+}
+
+/// Everything a [Stack] can be configured with through a [StackBuilder]
+#[derive(Clone, Debug)]
+pub(crate) struct Config {
+    pub(crate) bind_address: Option<IpAddr>,
+    pub(crate) family_policy: FamilyPolicy,
+    pub(crate) options: SocketOptions,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) dns_preference: DnsPreference,
+    pub(crate) numeric_hosts_only: bool,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) bind_device: Option<std::ffi::OsString>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) socket_hook: Option<SocketHook>,
+}
+
+/// An std::io::Error compatible error type expressing that an address of a family was used that
+/// the stack is configured not to use
+#[derive(Debug)]
+struct FamilyNotAllowed;
+
+impl std::fmt::Display for FamilyNotAllowed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Address family not allowed by the stack's configuration")
+    }
+}
+
+impl std::error::Error for FamilyNotAllowed {}
+
+impl Config {
+    pub(crate) const DEFAULT: Config = Config {
+        bind_address: None,
+        family_policy: FamilyPolicy::Any,
+        options: SocketOptions {
+            reuse_address: false,
+            ttl: None,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            tcp_nodelay: None,
+        },
+        connect_timeout: None,
+        dns_preference: DnsPreference::SystemOrder,
+        numeric_hosts_only: false,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        bind_device: None,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        socket_hook: None,
+    };
+
+    /// Whether addresses of the given address' family may be used
+    pub(crate) fn accepts(&self, addr: IpAddr) -> bool {
+        !matches!(
+            (self.family_policy, addr),
+            (FamilyPolicy::Ipv4Only, IpAddr::V6(_)) | (FamilyPolicy::Ipv6Only, IpAddr::V4(_))
+        )
+    }
+
+    /// Err out if the address' family may not be used
+    pub(crate) fn check(&self, addr: IpAddr) -> io::Result<()> {
+        if self.accepts(addr) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                FamilyNotAllowed,
+            ))
+        }
+    }
+
+    /// The address a socket is bound to for a given port
+    pub(crate) fn bind_address(&self, port: u16) -> io::Result<SocketAddr> {
+        let ip = match (self.bind_address, self.family_policy) {
+            (Some(ip), _) => {
+                self.check(ip)?;
+                ip
+            }
+            (None, FamilyPolicy::Ipv4Only) => Ipv4Addr::UNSPECIFIED.into(),
+            (None, _) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    /// The address an outgoing socket is bound to before connecting to the remote address
+    ///
+    /// That is only a concrete address if one is configured and of the remote's family.
+    pub(crate) fn source_address(&self, remote: SocketAddr) -> Option<SocketAddr> {
+        match (self.bind_address, remote) {
+            (Some(ip @ IpAddr::V4(_)), SocketAddr::V4(_))
+            | (Some(ip @ IpAddr::V6(_)), SocketAddr::V6(_))
+                if !ip.is_unspecified() =>
+            {
+                Some(SocketAddr::new(ip, 0))
+            }
+            _ => None,
+        }
+    }
+
+    /// Value of IPV6_V6ONLY for sockets bound to the given address, if it is to be set
+    pub(crate) fn v6only(&self, local: SocketAddr) -> Option<bool> {
+        match (self.family_policy, local) {
+            (FamilyPolicy::Ipv6Only, SocketAddr::V6(_)) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Builder for a [Stack] that deviates from the default behavior
+///
+/// A builder is obtained from [Stack::builder]; all settings not altered on the builder keep the
+/// behavior of [Stack::default].
+///
+/// ```
+/// # use std::time::Duration;
+/// let stack = mm_std_embedded_nal::Stack::builder()
+///     .family_policy(mm_std_embedded_nal::FamilyPolicy::Ipv4Only)
+///     .connect_timeout(Duration::from_secs(5))
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct StackBuilder {
+    config: Config,
+}
+
+impl StackBuilder {
+    pub(crate) fn new() -> Self {
+        Self {
+            config: Config::DEFAULT,
+        }
+    }
+
+    /// Set the address sockets are bound to in `bind` operations.
+    ///
+    /// Outgoing sockets (from `connect` operations) are bound to that address as well if it is of
+    /// the remote address' family and not unspecified; otherwise, their source address is picked
+    /// by the operating system.
+    ///
+    /// By default, the unspecified address of the family allowed by the [FamilyPolicy] is used.
+    pub fn bind_address(mut self, address: IpAddr) -> Self {
+        self.config.bind_address = Some(address);
+        self
+    }
+
+    /// Set which address families the stack uses. The default is [FamilyPolicy::Any].
+    pub fn family_policy(mut self, policy: FamilyPolicy) -> Self {
+        self.config.family_policy = policy;
+        self
+    }
+
+    /// Set the SO_REUSEADDR option on all UDP sockets before they are bound.
+    ///
+    /// (TCP listening sockets have that option set in any case, as they do in the standard
+    /// library).
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.config.options.reuse_address = reuse;
+        self
+    }
+
+    /// Set the time-to-live (or, on IPv6, the unicast hop limit) of all sockets.
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.config.options.ttl = Some(ttl);
+        self
+    }
+
+    /// Set the size of the operating system's receive buffer of all sockets.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.config.options.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the operating system's send buffer of all sockets.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.config.options.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Enable or disable Nagle's algorithm on all TCP connections, including accepted ones.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.config.options.tcp_nodelay = Some(nodelay);
+        self
+    }
+
+    /// Limit the time a TCP `connect` operation may block.
+    ///
+    /// By default, connecting blocks until the operating system gives up on the connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Set which address is returned from name resolution if any family is acceptable. The
+    /// default is [DnsPreference::SystemOrder].
+    pub fn dns_preference(mut self, preference: DnsPreference) -> Self {
+        self.config.dns_preference = preference;
+        self
+    }
+
+    /// Only resolve host names that are IP address literals, without consulting the system
+    /// resolver.
+    pub fn numeric_hosts_only(mut self, numeric_only: bool) -> Self {
+        self.config.numeric_hosts_only = numeric_only;
+        self
+    }
+
+    /// Pin every socket the stack creates to the named network interface, eg. `"eth0"`
+    /// (`SO_BINDTODEVICE`, Linux only).
+    ///
+    /// Traffic then only leaves through that interface, and only datagrams and connections that
+    /// arrive on it are accepted, independently of what the routing table would pick. Setting this
+    /// needs the `CAP_NET_RAW` capability on kernels before 5.7; creating sockets fails if the
+    /// interface does not exist.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn bind_device(mut self, interface: impl Into<std::ffi::OsString>) -> Self {
+        self.config.bind_device = Some(interface.into());
+        self
+    }
+
+    /// Run a callback on every socket the stack creates, after the configured options are applied
+    /// but before the socket is bound or connected (Linux only).
+    ///
+    /// The callback receives the socket's file descriptor and what it is going to be used for.
+    /// It can apply further configuration (eg. set a firewall mark or attach a BPF filter) or log
+    /// the creation. An error returned from the callback fails the operation that creates the
+    /// socket. Sockets accepted from a listener are not passed to the callback.
+    ///
+    /// ```
+    /// let stack = mm_std_embedded_nal::Stack::builder()
+    ///     .socket_hook(|fd, role| {
+    ///         use std::os::unix::io::AsRawFd;
+    ///         eprintln!("Created {role:?} socket {}", fd.as_raw_fd());
+    ///         Ok(())
+    ///     })
+    ///     .build();
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn socket_hook(
+        mut self,
+        hook: impl Fn(std::os::unix::io::BorrowedFd<'_>, SocketRole) -> io::Result<()>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.config.socket_hook = Some(SocketHook(std::sync::Arc::new(hook)));
+        self
+    }
+
+    /// Create a [Stack] with the configured settings.
+    pub fn build(self) -> Stack {
+        Stack {
+            config: self.config,
+        }
+    }
+}
@@ -1,8 +1,9 @@
+use crate::DnsPreference;
 use embedded_nal::{nb, AddrType, Dns};
 use std::error;
 use std::fmt::{self, Display, Formatter};
 use std::io::{Error, ErrorKind};
-use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
 
 /// An std::io::Error compatible error type constructable when to_socket_addrs comes up empty
 /// (because it does not produce an error of its own)
@@ -25,28 +26,55 @@ impl Dns for crate::Stack {
         hostname: &str,
         addr_type: AddrType,
     ) -> Result<IpAddr, nb::Error<Error>> {
+        let accept_v4 =
+            addr_type != AddrType::IPv6 && self.config.accepts(Ipv4Addr::UNSPECIFIED.into());
+        let accept_v6 =
+            addr_type != AddrType::IPv4 && self.config.accepts(Ipv6Addr::UNSPECIFIED.into());
+        let accept = |addr: &IpAddr| match addr {
+            IpAddr::V4(_) => accept_v4,
+            IpAddr::V6(_) => accept_v6,
+        };
+
+        if self.config.numeric_hosts_only {
+            return hostname
+                .parse()
+                .ok()
+                .filter(accept)
+                .ok_or_else(|| nb::Error::Other(Error::new(ErrorKind::NotFound, NotFound)));
+        }
+
         let with_fake_port = if hostname.find(':').is_some() {
             format!("[{}]:1234", hostname)
         } else {
             format!("{}:1234", hostname)
         };
 
-        let accept_v4 = addr_type != AddrType::IPv6;
-        let accept_v6 = addr_type != AddrType::IPv4;
-
-        for addr in with_fake_port.to_socket_addrs()? {
-            match addr {
-                SocketAddr::V4(v) if accept_v4 => {
-                    return Ok(v.ip().octets().into());
-                }
-                SocketAddr::V6(v) if accept_v6 => {
-                    return Ok(v.ip().octets().into());
-                }
-                _ => continue,
+        let mut found = with_fake_port
+            .to_socket_addrs()?
+            .map(|addr| addr.ip())
+            .filter(accept);
+
+        let result = match self.config.dns_preference {
+            DnsPreference::SystemOrder => found.next(),
+            DnsPreference::PreferIpv4 => {
+                let found: Vec<_> = found.collect();
+                found
+                    .iter()
+                    .find(|a| a.is_ipv4())
+                    .or(found.first())
+                    .copied()
             }
-        }
+            DnsPreference::PreferIpv6 => {
+                let found: Vec<_> = found.collect();
+                found
+                    .iter()
+                    .find(|a| a.is_ipv6())
+                    .or(found.first())
+                    .copied()
+            }
+        };
 
-        Err(nb::Error::Other(Error::new(ErrorKind::NotFound, NotFound)))
+        result.ok_or_else(|| nb::Error::Other(Error::new(ErrorKind::NotFound, NotFound)))
     }
 
     fn get_host_by_address(
@@ -8,27 +8,90 @@
 //!
 //! All implementations use `std::io::Error` as their error type.
 //!
+//! On Linux, the [poll] module allows waiting for several sockets at once without busy looping.
+//! Sockets passed in by a service manager (like systemd's socket activation) can be taken using
+//! the [activation] module, and the host's interfaces and addresses are listed by
+//! [`Stack::interfaces()`].
+//! With the `mio` feature enabled, the sockets can also be registered with a [mio] event loop
+//! directly, even before they are connected or bound.
+//! For hermetic tests, the [unix] module provides a stack that runs the UDP and TCP traits over
+//! Unix domain sockets in a directory.
+//! With the `embedded-nal-async` feature enabled, the same sockets are available through the
+//! [embedded-nal-async] traits by wrapping a stack in an `AsyncStack` (Linux only).
+//!
 //! [embedded-nal]: https://crates.io/crates/embedded-nal
+//! [embedded-nal-async]: https://crates.io/crates/embedded-nal-async
+//! [mio]: https://crates.io/crates/mio
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod activation;
+mod builder;
 mod conversion;
 mod dns;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod interfaces;
+#[cfg(feature = "mio")]
+mod mio_source;
+#[cfg(all(
+    feature = "embedded-nal-async",
+    any(target_os = "linux", target_os = "android")
+))]
+mod nal_async;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod poll;
+mod socket;
 mod tcp;
 mod udp;
+#[cfg(unix)]
+pub mod unix;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use builder::SocketRole;
+pub use builder::{DnsPreference, FamilyPolicy, StackBuilder};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use interfaces::Interface;
+#[cfg(all(
+    feature = "embedded-nal-async",
+    any(target_os = "linux", target_os = "android")
+))]
+pub use nal_async::{AsyncStack, ConnectedSocket, TcpConnection, UnconnectedSocket};
+pub use tcp::{TcpError, TcpSocket};
+pub use udp::UdpSocket;
 
 /// The operating system's network stack, implementing ``embedded_nal::UdpFullStack`` and others.
 ///
-/// The user may instantiate a stack using the `Stack::default()` function.
+/// The user may instantiate a stack using the `Stack::default()` function, or configure one
+/// using [`Stack::builder()`].
 ///
 /// The stack can be cloned, as it is not a resource that needs any synchronization. This is not
 /// made implicit as Copy, though (although there's not technical reason not to). That is to alert
 /// users to the difficulties that'd arise when copying around a stack rather than using it through
 /// some mechanism of synchronization (which is generally required with ``embedded_nal`` since
 /// version 0.3).
-#[derive(Clone, Default)]
-pub struct Stack;
+#[derive(Clone)]
+pub struct Stack {
+    config: builder::Config,
+}
+
+impl Stack {
+    /// Start configuring a stack that deviates from the defaults.
+    pub fn builder() -> StackBuilder {
+        StackBuilder::new()
+    }
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self {
+            config: builder::Config::DEFAULT,
+        }
+    }
+}
 
 #[deprecated(note = "Use Stack::default() instead.")]
-pub static STACK: Stack = Stack;
+pub static STACK: Stack = Stack {
+    config: builder::Config::DEFAULT,
+};
 
 /// An std::io::Error compatible error type returned when an operation is requested in the wrong
 /// sequence (where the "right" is create a socket, connect, any receive/send, and possibly close).
@@ -0,0 +1,83 @@
+//! Support for registering sockets with a [mio] event loop
+//!
+//! Both socket types implement [`mio::event::Source`]. Unlike registering their
+//! [`as_raw_fd()`](crate::UdpSocket::as_raw_fd) through [`mio::unix::SourceFd`], this keeps
+//! working when the operating system socket only comes into existence at a later `connect` or
+//! `bind` operation: The socket remembers how it was registered, and registers its new file
+//! descriptor then.
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+
+/// How a socket was registered with a [`Registry`]
+///
+/// This is kept around by sockets registered with mio, so that they can register their file
+/// descriptor whenever it changes.
+pub(crate) struct Registration {
+    registry: Registry,
+    token: Token,
+    interests: Interest,
+}
+
+impl Registration {
+    /// Register the socket's file descriptor (if there is any), and return a registration that
+    /// needs to be stored for [`.follow()`](Registration::follow).
+    pub(crate) fn register(
+        fd: Option<RawFd>,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<Self> {
+        if let Some(fd) = fd {
+            registry.register(&mut SourceFd(&fd), token, interests)?;
+        }
+        Ok(Self {
+            registry: registry.try_clone()?,
+            token,
+            interests,
+        })
+    }
+
+    /// Like [`.register()`](Registration::register), but for sockets that were registered before.
+    ///
+    /// The previous registration is passed in, as sockets that had no file descriptor when last
+    /// registered still need to be registered rather than re-registered.
+    pub(crate) fn reregister(
+        previous: Option<Self>,
+        fd: Option<RawFd>,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<Self> {
+        match (previous, fd) {
+            (Some(_), Some(fd)) => {
+                registry.reregister(&mut SourceFd(&fd), token, interests)?;
+                Ok(Self {
+                    registry: registry.try_clone()?,
+                    token,
+                    interests,
+                })
+            }
+            (_, fd) => Self::register(fd, registry, token, interests),
+        }
+    }
+
+    pub(crate) fn deregister(fd: Option<RawFd>, registry: &Registry) -> io::Result<()> {
+        match fd {
+            Some(fd) => registry.deregister(&mut SourceFd(&fd)),
+            None => Ok(()),
+        }
+    }
+
+    /// Register the socket's new file descriptor after a `connect` or `bind` operation.
+    ///
+    /// Any previous file descriptor was closed in that operation, which removes it from the
+    /// registry implicitly.
+    pub(crate) fn follow(&self, fd: RawFd) -> io::Result<()> {
+        self.registry
+            .register(&mut SourceFd(&fd), self.token, self.interests)
+    }
+}
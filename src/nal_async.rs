@@ -0,0 +1,428 @@
+//! Adapter that exposes a [Stack] through the [embedded_nal_async] traits
+//!
+//! The [AsyncStack] creates its sockets through the wrapped [Stack], and performs all operations
+//! through the [embedded_nal] methods of that stack. Whenever those report
+//! [`WouldBlock`](nb::Error::WouldBlock), the socket's file descriptor is waited for using the
+//! [async_io] reactor, which works with any executor.
+//!
+//! The unconnected UDP sockets additionally use `IP_PKTINFO` / `IPV6_PKTINFO` to report and set
+//! the local address of each datagram, as the [embedded_nal_async] traits require. Unlike the
+//! sockets of the [embedded_nal] traits, all UDP sockets here report the full length of
+//! datagrams that exceed the receive buffer, as the [embedded_nal_async] traits require.
+
+use crate::{Stack, TcpSocket, UdpSocket};
+use async_io::Async;
+use embedded_nal::nb;
+use std::io::{self, Error};
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// A [Stack] that implements the [embedded_nal_async] traits
+///
+/// It is created from a [Stack] (using [`AsyncStack::new()`] or `From`), whose configuration
+/// applies to all sockets it creates.
+#[derive(Clone, Default)]
+pub struct AsyncStack {
+    stack: Stack,
+}
+
+impl AsyncStack {
+    /// Wrap a stack, whose configuration then applies to all sockets created through this.
+    pub fn new(stack: Stack) -> Self {
+        Self { stack }
+    }
+}
+
+impl From<Stack> for AsyncStack {
+    fn from(stack: Stack) -> Self {
+        Self::new(stack)
+    }
+}
+
+/// A socket that is known to have a file descriptor, so that it can be waited on
+struct Registered<S>(S);
+
+impl AsRawFd for Registered<UdpSocket> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+            .as_raw_fd()
+            .expect("Only connected or bound sockets are registered")
+    }
+}
+
+impl AsRawFd for Registered<TcpSocket> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+            .as_raw_fd()
+            .expect("Only connected or bound sockets are registered")
+    }
+}
+
+/// Turn the result of an `nb` operation into one that [async_io] can retry.
+fn to_io<T, E: Into<Error>>(result: nb::Result<T, E>) -> io::Result<T> {
+    result.map_err(|e| match e {
+        nb::Error::WouldBlock => io::ErrorKind::WouldBlock.into(),
+        nb::Error::Other(e) => e.into(),
+    })
+}
+
+/// A UDP socket as created by [`connect`](embedded_nal_async::UdpStack::connect) or
+/// [`connect_from`](embedded_nal_async::UdpStack::connect_from)
+pub struct ConnectedSocket {
+    socket: Async<Registered<UdpSocket>>,
+    stack: Stack,
+}
+
+/// A UDP socket as created by [`bind_single`](embedded_nal_async::UdpStack::bind_single) or
+/// [`bind_multiple`](embedded_nal_async::UdpStack::bind_multiple)
+///
+/// The same type serves both purposes: The socket always reports the local address of a received
+/// datagram, and always sends from the given local address.
+///
+/// When sending, a local port of 0 stands for the bound port, and an unspecified local address
+/// lets the operating system pick the source address. Other local addresses need to be the bound
+/// address (or of its family, if the socket is bound to an unspecified address); any other fails
+/// with an [`InvalidInput`](io::ErrorKind::InvalidInput) error.
+pub struct UnconnectedSocket {
+    socket: Async<Registered<UdpSocket>>,
+    stack: Stack,
+    // Storing this so we can check local addresses, and return a full SocketAddr, even though
+    // pktinfo doesn't provide the port
+    bound: SocketAddr,
+}
+
+impl embedded_nal_async::UdpStack for AsyncStack {
+    type Error = Error;
+    type Connected = ConnectedSocket;
+    type UniquelyBound = UnconnectedSocket;
+    type MultiplyBound = UnconnectedSocket;
+
+    async fn connect_from(
+        &self,
+        local: SocketAddr,
+        remote: SocketAddr,
+    ) -> Result<(SocketAddr, Self::Connected), Self::Error> {
+        let stack = self.stack.clone();
+        stack.config.check(local.ip())?;
+        stack.config.check(remote.ip())?;
+
+        let sock = crate::socket::udp_connect(local, remote, &stack.config)?;
+        let final_local = sock.local_addr()?;
+
+        Ok((
+            final_local,
+            ConnectedSocket {
+                socket: Async::new(Registered(UdpSocket::try_from(sock)?))?,
+                stack,
+            },
+        ))
+    }
+
+    async fn bind_single(
+        &self,
+        local: SocketAddr,
+    ) -> Result<(SocketAddr, Self::UniquelyBound), Self::Error> {
+        self.bind(local)
+    }
+
+    async fn bind_multiple(&self, local: SocketAddr) -> Result<Self::MultiplyBound, Self::Error> {
+        Ok(self.bind(local)?.1)
+    }
+}
+
+impl AsyncStack {
+    /// Bind a UDP socket that reports the local address of received datagrams
+    fn bind(&self, local: SocketAddr) -> io::Result<(SocketAddr, UnconnectedSocket)> {
+        use nix::sys::socket::{setsockopt, sockopt};
+
+        let stack = self.stack.clone();
+        stack.config.check(local.ip())?;
+
+        let sock = crate::socket::udp_bind(local, &stack.config)?;
+        let final_local = sock.local_addr()?;
+        match final_local {
+            SocketAddr::V4(_) => setsockopt(&sock, sockopt::Ipv4PacketInfo, &true)?,
+            SocketAddr::V6(_) => setsockopt(&sock, sockopt::Ipv6RecvPacketInfo, &true)?,
+        }
+
+        Ok((
+            final_local,
+            UnconnectedSocket {
+                socket: Async::new(Registered(UdpSocket::try_from(sock)?))?,
+                stack,
+                bound: final_local,
+            },
+        ))
+    }
+}
+
+impl embedded_nal_async::ConnectedUdp for ConnectedSocket {
+    type Error = Error;
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        use embedded_nal::UdpClientStack;
+
+        let Self { socket, stack } = self;
+        socket
+            .write_with_mut(|s| to_io(stack.send(&mut s.0, data)))
+            .await
+    }
+
+    async fn receive_into(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        // Not going through the stack's receive, as that can not report the full length of
+        // truncated datagrams.
+        self.socket
+            .read_with(|s| {
+                Ok(nix::sys::socket::recv(
+                    s.as_raw_fd(),
+                    buffer,
+                    nix::sys::socket::MsgFlags::MSG_TRUNC,
+                )?)
+            })
+            .await
+    }
+}
+
+impl embedded_nal_async::UnconnectedUdp for UnconnectedSocket {
+    type Error = Error;
+
+    async fn send(
+        &mut self,
+        local: SocketAddr,
+        remote: SocketAddr,
+        data: &[u8],
+    ) -> Result<(), Self::Error> {
+        use embedded_nal::UdpFullStack;
+
+        self.check_local(local)?;
+        self.stack.config.check(remote.ip())?;
+
+        // Packet info is only needed to pick one of the addresses an unspecified address stands
+        // for
+        if local.ip().is_unspecified() || !self.bound.ip().is_unspecified() {
+            let Self { socket, stack, .. } = self;
+            return socket
+                .write_with_mut(|s| to_io(stack.send_to(&mut s.0, remote, data)))
+                .await;
+        }
+
+        let remote = nix::sys::socket::SockaddrStorage::from(remote);
+        let sent_len = match local.ip() {
+            IpAddr::V6(local) => {
+                let pktinfo = nix::libc::in6_pktinfo {
+                    ipi6_addr: nix::libc::in6_addr {
+                        s6_addr: local.octets(),
+                    },
+                    ipi6_ifindex: 0,
+                };
+                let control = [nix::sys::socket::ControlMessage::Ipv6PacketInfo(&pktinfo)];
+                self.socket
+                    .write_with(|s| send_with_control(s.as_raw_fd(), data, &control, &remote))
+                    .await?
+            }
+            IpAddr::V4(local) => {
+                // s_addr is in network byte order
+                let local = nix::libc::in_addr {
+                    s_addr: u32::from(local).to_be(),
+                };
+                let pktinfo = nix::libc::in_pktinfo {
+                    ipi_spec_dst: local,
+                    ipi_addr: local,
+                    ipi_ifindex: 0,
+                };
+                let control = [nix::sys::socket::ControlMessage::Ipv4PacketInfo(&pktinfo)];
+                self.socket
+                    .write_with(|s| send_with_control(s.as_raw_fd(), data, &control, &remote))
+                    .await?
+            }
+        };
+
+        if sent_len != data.len() {
+            return Err(Error::new(
+                io::ErrorKind::WriteZero,
+                "Datagram was not sent in a single operation",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn receive_into(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, SocketAddr, SocketAddr), Self::Error> {
+        let port = self.bound.port();
+        self.socket
+            .read_with(|s| {
+                let mut iov = [io::IoSliceMut::new(buffer)];
+                let mut cmsg = nix::cmsg_space!(nix::libc::in6_pktinfo);
+                let received = nix::sys::socket::recvmsg::<nix::sys::socket::SockaddrStorage>(
+                    s.as_raw_fd(),
+                    &mut iov,
+                    Some(&mut cmsg),
+                    nix::sys::socket::MsgFlags::MSG_TRUNC,
+                )?;
+                let local: IpAddr = match received.cmsgs().next() {
+                    Some(nix::sys::socket::ControlMessageOwned::Ipv6PacketInfo(pi)) => {
+                        std::net::Ipv6Addr::from(pi.ipi6_addr.s6_addr).into()
+                    }
+                    Some(nix::sys::socket::ControlMessageOwned::Ipv4PacketInfo(pi)) => {
+                        // s_addr is in network byte order
+                        std::net::Ipv4Addr::from(u32::from_be(pi.ipi_addr.s_addr)).into()
+                    }
+                    _ => {
+                        return Err(Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Operating system failed to send packet info",
+                        ))
+                    }
+                };
+                let remote = received
+                    .address
+                    .and_then(|a| match (a.as_sockaddr_in6(), a.as_sockaddr_in()) {
+                        (Some(a), _) => Some(std::net::SocketAddrV6::from(*a).into()),
+                        (_, Some(a)) => Some(std::net::SocketAddrV4::from(*a).into()),
+                        _ => None,
+                    })
+                    .ok_or_else(|| {
+                        Error::new(io::ErrorKind::InvalidData, "Unexpected address type")
+                    })?;
+                Ok((received.bytes, SocketAddr::new(local, port), remote))
+            })
+            .await
+    }
+}
+
+impl UnconnectedSocket {
+    /// Err out unless the socket can send from `local`.
+    fn check_local(&self, local: SocketAddr) -> io::Result<()> {
+        let mismatch = |message| Err(Error::new(io::ErrorKind::InvalidInput, message));
+        if local.port() != 0 && local.port() != self.bound.port() {
+            return mismatch("Packets can only be sent from the locally bound to port");
+        }
+        if local.is_ipv4() != self.bound.is_ipv4() {
+            return mismatch("Packets can only be sent from the locally bound to address family");
+        }
+        if !self.bound.ip().is_unspecified()
+            && !local.ip().is_unspecified()
+            && local.ip() != self.bound.ip()
+        {
+            return mismatch("Packets can only be sent from the locally bound to address");
+        }
+        Ok(())
+    }
+}
+
+fn send_with_control(
+    fd: RawFd,
+    data: &[u8],
+    control: &[nix::sys::socket::ControlMessage<'_>],
+    remote: &nix::sys::socket::SockaddrStorage,
+) -> io::Result<usize> {
+    Ok(nix::sys::socket::sendmsg(
+        fd,
+        &[io::IoSlice::new(data)],
+        control,
+        nix::sys::socket::MsgFlags::empty(),
+        Some(remote),
+    )?)
+}
+
+impl embedded_nal_async::TcpConnect for AsyncStack {
+    type Error = Error;
+
+    type Connection<'a> = TcpConnection;
+
+    async fn connect<'a>(&'a self, remote: SocketAddr) -> Result<Self::Connection<'a>, Error> {
+        self.stack.config.check(remote.ip())?;
+
+        let (stream, in_progress) = crate::socket::tcp_connect_start(remote, &self.stack.config)?;
+        let stream = Async::new(stream)?;
+
+        if in_progress {
+            let connect = async {
+                stream.writable().await?;
+                match stream.get_ref().take_error()? {
+                    Some(e) => Err(e),
+                    None => Ok(()),
+                }
+            };
+            match self.stack.config.connect_timeout {
+                Some(timeout) => {
+                    futures_lite::future::or(connect, async {
+                        async_io::Timer::after(timeout).await;
+                        Err(Error::new(
+                            io::ErrorKind::TimedOut,
+                            "Connection attempt timed out",
+                        ))
+                    })
+                    .await?
+                }
+                None => connect.await?,
+            }
+        }
+
+        Ok(TcpConnection {
+            socket: Async::new(Registered(TcpSocket::try_from(stream.into_inner()?)?))?,
+            stack: self.stack.clone(),
+        })
+    }
+}
+
+/// A TCP connection as created by [`connect`](embedded_nal_async::TcpConnect::connect)
+pub struct TcpConnection {
+    socket: Async<Registered<TcpSocket>>,
+    stack: Stack,
+}
+
+impl embedded_io_async::ErrorType for TcpConnection {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for TcpConnection {
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        use embedded_nal::TcpClientStack;
+
+        let Self { socket, stack } = self;
+        socket
+            .read_with_mut(|s| to_io(stack.receive(&mut s.0, buffer)))
+            .await
+    }
+}
+
+impl embedded_io_async::Write for TcpConnection {
+    async fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+        use embedded_nal::TcpClientStack;
+
+        let Self { socket, stack } = self;
+        socket
+            .write_with_mut(|s| to_io(stack.send(&mut s.0, buffer)))
+            .await
+    }
+}
+
+impl embedded_nal_async::Dns for AsyncStack {
+    type Error = Error;
+
+    async fn get_host_by_name(
+        &self,
+        host: &str,
+        addr_type: embedded_nal::AddrType,
+    ) -> Result<IpAddr, Error> {
+        let mut stack = self.stack.clone();
+        let host = host.to_owned();
+        // Name resolution through the system resolver blocks
+        blocking::unblock(move || {
+            to_io(embedded_nal::Dns::get_host_by_name(
+                &mut stack, &host, addr_type,
+            ))
+        })
+        .await
+    }
+
+    async fn get_host_by_address(&self, addr: IpAddr, result: &mut [u8]) -> Result<usize, Error> {
+        let mut stack = self.stack.clone();
+        to_io(embedded_nal::Dns::get_host_by_address(
+            &mut stack, addr, result,
+        ))
+    }
+}
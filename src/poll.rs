@@ -0,0 +1,224 @@
+//! Waiting for any of several sockets to become ready
+//!
+//! The `nb` based traits of [embedded_nal] only ever report [`nb::Error::WouldBlock`]; they leave
+//! it to the application to find out when to try again. The [`Poll`] set provided here blocks
+//! (using `epoll`) until any of a number of [`UdpSocket`]s and [`TcpSocket`]s is ready to make
+//! progress, or until a timeout expires.
+//!
+//! [`nb::Error::WouldBlock`]: embedded_nal::nb::Error::WouldBlock
+
+use crate::{TcpSocket, UdpSocket};
+use nix::libc;
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags};
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::{BorrowedFd, RawFd};
+use std::time::Duration;
+
+/// Kind of readiness a socket is waited for
+///
+/// Interests can be combined using `|`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Interest(u8);
+
+impl Interest {
+    /// Wait for the socket to be readable, ie. for a receive or accept operation to make progress
+    pub const READABLE: Interest = Interest(1);
+    /// Wait for the socket to be writable, ie. for a send operation to make progress
+    pub const WRITABLE: Interest = Interest(2);
+
+    /// Whether this includes [`READABLE`](Self::READABLE)
+    pub fn is_readable(self) -> bool {
+        self.0 & Self::READABLE.0 != 0
+    }
+
+    /// Whether this includes [`WRITABLE`](Self::WRITABLE)
+    pub fn is_writable(self) -> bool {
+        self.0 & Self::WRITABLE.0 != 0
+    }
+
+    fn epoll_flags(self) -> EpollFlags {
+        let mut flags = EpollFlags::empty();
+        if self.is_readable() {
+            flags |= EpollFlags::EPOLLIN;
+        }
+        if self.is_writable() {
+            flags |= EpollFlags::EPOLLOUT;
+        }
+        flags
+    }
+}
+
+impl core::ops::BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, other: Interest) -> Interest {
+        Interest(self.0 | other.0)
+    }
+}
+
+/// Any socket of this crate that can be waited for in a [`Poll`] set
+#[derive(Copy, Clone)]
+pub enum Source<'a> {
+    Udp(&'a UdpSocket),
+    Tcp(&'a TcpSocket),
+}
+
+impl Source<'_> {
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        match self {
+            Source::Udp(s) => s.as_raw_fd(),
+            Source::Tcp(s) => s.as_raw_fd(),
+        }
+    }
+}
+
+impl<'a> From<&'a UdpSocket> for Source<'a> {
+    fn from(socket: &'a UdpSocket) -> Self {
+        Source::Udp(socket)
+    }
+}
+
+impl<'a> From<&'a TcpSocket> for Source<'a> {
+    fn from(socket: &'a TcpSocket) -> Self {
+        Source::Tcp(socket)
+    }
+}
+
+/// Readiness of one of the sources passed to [`Poll::wait`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Readiness {
+    /// Position of the source in the slice passed to [`Poll::wait`]
+    pub index: usize,
+    /// Whether a receive or accept operation on the socket will make progress
+    pub readable: bool,
+    /// Whether a send operation on the socket will make progress
+    pub writable: bool,
+}
+
+/// A set of sockets that can be waited for together
+///
+/// Unlike the sets of `mio` or of `epoll` itself, this does not keep the sockets registered
+/// between calls: The sockets are passed in anew on every [`.wait()`](Poll::wait) call. That
+/// allows waiting for sockets that do not have an operating system socket yet (because they
+/// are neither connected nor bound), and following them once they do. It also means that the
+/// sockets can be used freely between the calls.
+pub struct Poll {
+    epoll: Epoll,
+    // The file descriptors that were registered with the epoll instance on the last call, along
+    // with the flags they were registered with.
+    registered: HashMap<RawFd, EpollFlags>,
+    events: Vec<EpollEvent>,
+}
+
+impl Poll {
+    /// Create an empty set.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            epoll: Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?,
+            registered: HashMap::new(),
+            events: Vec::new(),
+        })
+    }
+
+    /// Block until any of the given sources is ready for any of the operations it is given with,
+    /// or until the timeout expires.
+    ///
+    /// All sources that are ready are reported; if the timeout expired, the result is empty.
+    /// Readiness is only a hint: An operation may still return
+    /// [`WouldBlock`](embedded_nal::nb::Error::WouldBlock) after it was reported ready.
+    ///
+    /// Sockets that are in an error state are reported as ready for all their interests, as the
+    /// operations will not block but report the error. Likewise, sockets that are neither
+    /// connected nor bound are reported ready right away: Any operation on them will fail
+    /// without blocking.
+    ///
+    /// Timeouts are rounded up to milliseconds; timeouts longer than `epoll` can express (about
+    /// 24 days) are shortened to that.
+    pub fn wait(
+        &mut self,
+        sources: &[(Source<'_>, Interest)],
+        timeout: Option<Duration>,
+    ) -> io::Result<Vec<Readiness>> {
+        let mut ready = Vec::new();
+        let mut wanted: HashMap<RawFd, EpollFlags> = HashMap::new();
+
+        for (index, (source, interest)) in sources.iter().enumerate() {
+            match source.as_raw_fd() {
+                Some(fd) => {
+                    *wanted.entry(fd).or_insert(EpollFlags::empty()) |= interest.epoll_flags()
+                }
+                None => ready.push(Readiness {
+                    index,
+                    readable: interest.is_readable(),
+                    writable: interest.is_writable(),
+                }),
+            }
+        }
+
+        for (&fd, &flags) in wanted.iter() {
+            // The file descriptor is guaranteed to be open for the duration of this function, as
+            // it belongs to a socket that is borrowed by `sources`.
+            let fd_borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+            let mut event = EpollEvent::new(flags, fd as u64);
+            // Even when it was registered in an earlier call, the socket may have been closed in
+            // the meantime (which removes it from the epoll set), and another socket may have
+            // gotten the same file descriptor. Modifying it unconditionally ensures that the
+            // socket currently behind the file descriptor is registered.
+            match self.epoll.modify(fd_borrowed, &mut event) {
+                Err(nix::errno::Errno::ENOENT) => self.epoll.add(fd_borrowed, event)?,
+                result => result?,
+            }
+        }
+        for (&fd, _) in self.registered.iter() {
+            if !wanted.contains_key(&fd) {
+                // Unlike in the loop above, the file descriptor may be closed already (or belong
+                // to a different socket), in which case this fails harmlessly -- or
+                // deregisters a socket that would need to be registered again anyway.
+                let fd_borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+                let _ = self.epoll.delete(fd_borrowed);
+            }
+        }
+        self.registered = wanted;
+
+        let timeout = if !ready.is_empty() {
+            0
+        } else {
+            match timeout {
+                // Rounding up: Waking up early would make users spin until the timeout is over.
+                Some(t) => t
+                    .checked_add(Duration::from_nanos(999_999))
+                    .and_then(|t| libc::c_int::try_from(t.as_millis()).ok())
+                    .unwrap_or(libc::c_int::MAX) as isize,
+                None => -1,
+            }
+        };
+
+        self.events
+            .resize(self.registered.len().max(1), EpollEvent::empty());
+        let count = self.epoll.wait(&mut self.events, timeout)?;
+
+        for event in &self.events[..count] {
+            let fd = event.data() as RawFd;
+            let flags = event.events();
+            let error = flags.intersects(EpollFlags::EPOLLERR | EpollFlags::EPOLLHUP);
+            for (index, (source, interest)) in sources.iter().enumerate() {
+                if source.as_raw_fd() != Some(fd) {
+                    continue;
+                }
+                let readiness = Readiness {
+                    index,
+                    readable: interest.is_readable()
+                        && (error || flags.contains(EpollFlags::EPOLLIN)),
+                    writable: interest.is_writable()
+                        && (error || flags.contains(EpollFlags::EPOLLOUT)),
+                };
+                if readiness.readable || readiness.writable {
+                    ready.push(readiness);
+                }
+            }
+        }
+
+        Ok(ready)
+    }
+}
@@ -0,0 +1,256 @@
+//! Creation of operating system sockets according to a stack's configuration
+//!
+//! All sockets returned from here are in non-blocking mode.
+//!
+//! On Linux, sockets are created step by step, so that options can be applied before they are
+//! bound or connected. Elsewhere, the standard library's constructors are used, and options that
+//! would need to be set before binding are unsupported.
+
+use crate::builder::Config;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::builder::SocketRole;
+use std::io;
+use std::net::{self, SocketAddr, TcpListener, TcpStream};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod os {
+    use super::*;
+    use nix::sys::socket::SockaddrStorage;
+    use nix::sys::socket::{self, setsockopt, sockopt, AddressFamily, SockFlag, SockType};
+    use std::os::unix::io::{AsFd, AsRawFd, OwnedFd};
+
+    /// Backlog of listening sockets, as used by the standard library
+    const BACKLOG: usize = 128;
+
+    fn new_socket(
+        addr: SocketAddr,
+        ty: SockType,
+        role: SocketRole,
+        config: &Config,
+    ) -> io::Result<OwnedFd> {
+        let family = match addr {
+            SocketAddr::V4(_) => AddressFamily::Inet,
+            SocketAddr::V6(_) => AddressFamily::Inet6,
+        };
+        let fd = socket::socket(family, ty, SockFlag::SOCK_CLOEXEC, None)?;
+
+        let options = &config.options;
+        if let Some(ttl) = options.ttl {
+            match addr {
+                SocketAddr::V4(_) => setsockopt(&fd, sockopt::Ipv4Ttl, &(ttl as _))?,
+                SocketAddr::V6(_) => setsockopt(&fd, sockopt::Ipv6Ttl, &(ttl as _))?,
+            }
+        }
+        if let Some(size) = options.recv_buffer_size {
+            setsockopt(&fd, sockopt::RcvBuf, &size)?;
+        }
+        if let Some(size) = options.send_buffer_size {
+            setsockopt(&fd, sockopt::SndBuf, &size)?;
+        }
+        if let (SockType::Stream, Some(nodelay)) = (ty, options.tcp_nodelay) {
+            setsockopt(&fd, sockopt::TcpNoDelay, &nodelay)?;
+        }
+        if let Some(device) = &config.bind_device {
+            setsockopt(&fd, sockopt::BindToDevice, device)?;
+        }
+
+        if let Some(hook) = &config.socket_hook {
+            (hook.0)(fd.as_fd(), role)?;
+        }
+
+        Ok(fd)
+    }
+
+    fn bind(fd: &OwnedFd, local: SocketAddr, config: &Config) -> io::Result<()> {
+        if let Some(v6only) = config.v6only(local) {
+            setsockopt(fd, sockopt::Ipv6V6Only, &v6only)?;
+        }
+        socket::bind(fd.as_raw_fd(), &SockaddrStorage::from(local))?;
+        Ok(())
+    }
+
+    pub(crate) fn udp_bind(local: SocketAddr, config: &Config) -> io::Result<net::UdpSocket> {
+        let fd = new_socket(local, SockType::Datagram, SocketRole::UdpBound, config)?;
+        if config.options.reuse_address {
+            setsockopt(&fd, sockopt::ReuseAddr, &true)?;
+        }
+        bind(&fd, local, config)?;
+
+        let sock = net::UdpSocket::from(fd);
+        sock.set_nonblocking(true)?;
+        Ok(sock)
+    }
+
+    pub(crate) fn udp_connect(
+        local: SocketAddr,
+        remote: SocketAddr,
+        config: &Config,
+    ) -> io::Result<net::UdpSocket> {
+        let fd = new_socket(remote, SockType::Datagram, SocketRole::UdpConnected, config)?;
+        bind(&fd, local, config)?;
+        socket::connect(fd.as_raw_fd(), &SockaddrStorage::from(remote))?;
+
+        let sock = net::UdpSocket::from(fd);
+        sock.set_nonblocking(true)?;
+        Ok(sock)
+    }
+
+    pub(crate) fn tcp_listen(local: SocketAddr, config: &Config) -> io::Result<TcpListener> {
+        let fd = new_socket(local, SockType::Stream, SocketRole::TcpListener, config)?;
+        setsockopt(&fd, sockopt::ReuseAddr, &true)?;
+        bind(&fd, local, config)?;
+        socket::listen(&fd, BACKLOG)?;
+
+        let sock = TcpListener::from(fd);
+        sock.set_nonblocking(true)?;
+        Ok(sock)
+    }
+
+    /// Start connecting a TCP socket.
+    ///
+    /// The returned boolean indicates whether the connection is still in progress; if so, the
+    /// socket becomes writable when it is complete, at which point its error needs to be checked
+    /// (see [`tcp_connect()`]).
+    pub(crate) fn tcp_connect_start(
+        remote: SocketAddr,
+        config: &Config,
+    ) -> io::Result<(TcpStream, bool)> {
+        use nix::errno::Errno;
+
+        let fd = new_socket(remote, SockType::Stream, SocketRole::TcpConnection, config)?;
+        if let Some(local) = config.source_address(remote) {
+            bind(&fd, local, config)?;
+        }
+
+        let sock = TcpStream::from(fd);
+        sock.set_nonblocking(true)?;
+
+        match socket::connect(sock.as_raw_fd(), &SockaddrStorage::from(remote)) {
+            Ok(()) => Ok((sock, false)),
+            Err(Errno::EINPROGRESS) | Err(Errno::EINTR) => Ok((sock, true)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) fn tcp_connect(remote: SocketAddr, config: &Config) -> io::Result<TcpStream> {
+        use nix::errno::Errno;
+        use nix::poll::{poll, PollFd, PollFlags};
+
+        // Connecting in non-blocking mode even when there is no timeout, as that makes the
+        // connection attempt uninterruptible by signals.
+        let (sock, in_progress) = tcp_connect_start(remote, config)?;
+        if !in_progress {
+            return Ok(sock);
+        }
+
+        let deadline = config
+            .connect_timeout
+            .map(|t| std::time::Instant::now() + t);
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                    // Rounding up, so that the loop does not spin when less than a millisecond is
+                    // left
+                    i32::try_from(
+                        (remaining + std::time::Duration::from_nanos(999_999)).as_millis(),
+                    )
+                    .unwrap_or(i32::MAX)
+                }
+                None => -1,
+            };
+            let mut fds = [PollFd::new(&sock, PollFlags::POLLOUT)];
+            match poll(&mut fds, timeout) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Connection attempt timed out",
+                    ))
+                }
+                Ok(_) => break,
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        match sock.take_error()? {
+            Some(e) => Err(e),
+            None => Ok(sock),
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod os {
+    use super::*;
+
+    /// Err out if options are configured that can not be applied to the standard library's
+    /// sockets.
+    fn check_unsupported(local: SocketAddr, config: &Config) -> io::Result<()> {
+        if config.options.reuse_address
+            || config.options.recv_buffer_size.is_some()
+            || config.options.send_buffer_size.is_some()
+            || config.v6only(local).is_some()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Socket options not supported on this platform",
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn udp_bind(local: SocketAddr, config: &Config) -> io::Result<net::UdpSocket> {
+        check_unsupported(local, config)?;
+        let sock = net::UdpSocket::bind(local)?;
+        if let Some(ttl) = config.options.ttl {
+            sock.set_ttl(ttl)?;
+        }
+        sock.set_nonblocking(true)?;
+        Ok(sock)
+    }
+
+    pub(crate) fn udp_connect(
+        local: SocketAddr,
+        remote: SocketAddr,
+        config: &Config,
+    ) -> io::Result<net::UdpSocket> {
+        let sock = udp_bind(local, config)?;
+        sock.connect(remote)?;
+        Ok(sock)
+    }
+
+    pub(crate) fn tcp_listen(local: SocketAddr, config: &Config) -> io::Result<TcpListener> {
+        check_unsupported(local, config)?;
+        let sock = TcpListener::bind(local)?;
+        if let Some(ttl) = config.options.ttl {
+            sock.set_ttl(ttl)?;
+        }
+        sock.set_nonblocking(true)?;
+        Ok(sock)
+    }
+
+    pub(crate) fn tcp_connect(remote: SocketAddr, config: &Config) -> io::Result<TcpStream> {
+        check_unsupported(remote, config)?;
+        if config.source_address(remote).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Binding outgoing TCP connections not supported on this platform",
+            ));
+        }
+        let sock = match config.connect_timeout {
+            Some(timeout) => TcpStream::connect_timeout(&remote, timeout)?,
+            None => TcpStream::connect(remote)?,
+        };
+        if let Some(ttl) = config.options.ttl {
+            sock.set_ttl(ttl)?;
+        }
+        if let Some(nodelay) = config.options.tcp_nodelay {
+            sock.set_nodelay(nodelay)?;
+        }
+        sock.set_nonblocking(true)?;
+        Ok(sock)
+    }
+}
+
+pub(crate) use os::*;
@@ -2,7 +2,7 @@ use crate::SocketState;
 use embedded_nal::nb;
 use embedded_nal::{TcpClientStack, TcpFullStack};
 use std::io::{Error, Read, Write};
-use std::net::{self, IpAddr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::net::{SocketAddr, TcpListener, TcpStream};
 
 #[derive(Debug)]
 pub struct TcpError(pub Error);
@@ -13,8 +13,14 @@ impl From<Error> for TcpError {
     }
 }
 
+impl From<TcpError> for Error {
+    fn from(e: TcpError) -> Self {
+        e.0
+    }
+}
+
 impl TcpError {
-    fn to_nb(e: Error) -> nb::Error<Self> {
+    pub(crate) fn to_nb(e: Error) -> nb::Error<Self> {
         use std::io::ErrorKind::{TimedOut, WouldBlock};
         match e.kind() {
             WouldBlock | TimedOut => nb::Error::WouldBlock,
@@ -34,21 +40,37 @@ impl embedded_nal::TcpError for TcpError {
 
 pub struct TcpSocket {
     state: SocketState<TcpStream, TcpListener>,
+    #[cfg(feature = "mio")]
+    registration: Option<crate::mio_source::Registration>,
 }
 
 impl TcpSocket {
     fn new() -> Self {
         Self {
             state: SocketState::new(),
+            #[cfg(feature = "mio")]
+            registration: None,
         }
     }
 
     fn connected(s: TcpStream) -> Self {
         Self {
             state: SocketState::Connected(s),
+            #[cfg(feature = "mio")]
+            registration: None,
         }
     }
 
+    /// Replace the socket's state after it has been connected or bound.
+    fn set_state(&mut self, state: SocketState<TcpStream, TcpListener>) -> Result<(), Error> {
+        self.state = state;
+        #[cfg(feature = "mio")]
+        if let (Some(registration), Some(fd)) = (&self.registration, self.as_raw_fd()) {
+            registration.follow(fd)?;
+        }
+        Ok(())
+    }
+
     /// Return the raw file descriptor underlying the current socket.
     ///
     /// This is primarily intended for use with `select` style mechanisms: Any of the `nb` methods
@@ -70,6 +92,73 @@ impl TcpSocket {
             SocketState::Building => None,
         }
     }
+
+    /// Borrow the file descriptor underlying the current socket.
+    ///
+    /// This behaves like [`.as_raw_fd()`](TcpSocket::as_raw_fd), but allows passing the socket to
+    /// libraries that take a [std::os::unix::io::AsFd].
+    #[cfg(any(unix, target_os = "wasi"))]
+    pub fn as_fd(&self) -> Option<std::os::unix::io::BorrowedFd<'_>> {
+        use std::os::unix::io::AsFd;
+
+        match &self.state {
+            SocketState::Connected(s) => Some(s.as_fd()),
+            SocketState::Bound(s) => Some(s.as_fd()),
+            SocketState::Building => None,
+        }
+    }
+
+    /// Take the standard library stream out of a connected socket.
+    ///
+    /// The stream is returned unmodified (in particular, it stays in non-blocking mode). If the
+    /// socket is not connected, the original socket is returned as the error.
+    pub fn try_into_std_stream(self) -> Result<TcpStream, Self> {
+        match self.state {
+            SocketState::Connected(s) => Ok(s),
+            _ => Err(self),
+        }
+    }
+
+    /// Take the standard library listener out of a bound socket.
+    ///
+    /// The listener is returned unmodified (in particular, it stays in non-blocking mode). If the
+    /// socket is not bound, the original socket is returned as the error.
+    pub fn try_into_std_listener(self) -> Result<TcpListener, Self> {
+        match self.state {
+            SocketState::Bound(s) => Ok(s),
+            _ => Err(self),
+        }
+    }
+}
+
+/// Wrap a connected standard library stream.
+///
+/// The stream is set to non-blocking mode, and can be used with the [TcpClientStack] methods.
+///
+/// This is `TryFrom` rather than `From`, as setting the socket to non-blocking mode can fail.
+impl TryFrom<TcpStream> for TcpSocket {
+    type Error = Error;
+
+    fn try_from(stream: TcpStream) -> Result<Self, Error> {
+        stream.set_nonblocking(true)?;
+        Ok(TcpSocket::connected(stream))
+    }
+}
+
+/// Wrap a standard library listener.
+///
+/// The listener is set to non-blocking mode, and can be used with [TcpFullStack::accept].
+///
+/// This is `TryFrom` rather than `From`, as setting the socket to non-blocking mode can fail.
+impl TryFrom<TcpListener> for TcpSocket {
+    type Error = Error;
+
+    fn try_from(listener: TcpListener) -> Result<Self, Error> {
+        listener.set_nonblocking(true)?;
+        let mut socket = TcpSocket::new();
+        socket.set_state(SocketState::Bound(listener))?;
+        Ok(socket)
+    }
 }
 
 impl TcpClientStack for crate::Stack {
@@ -85,11 +174,13 @@ impl TcpClientStack for crate::Stack {
         socket: &mut TcpSocket,
         remote: SocketAddr,
     ) -> nb::Result<(), Self::Error> {
-        let soc = TcpStream::connect(remote).map_err(Self::Error::from)?;
+        self.config.check(remote.ip()).map_err(Self::Error::from)?;
 
-        soc.set_nonblocking(true).map_err(Self::Error::from)?;
+        let soc = crate::socket::tcp_connect(remote, &self.config).map_err(Self::Error::from)?;
 
-        socket.state = SocketState::Connected(soc);
+        socket
+            .set_state(SocketState::Connected(soc))
+            .map_err(Self::Error::from)?;
         Ok(())
     }
 
@@ -119,13 +210,11 @@ impl TcpClientStack for crate::Stack {
 
 impl TcpFullStack for crate::Stack {
     fn bind(&mut self, socket: &mut TcpSocket, port: u16) -> Result<(), Self::Error> {
-        let anyaddressthisport = net::SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+        let anyaddressthisport = self.config.bind_address(port)?;
 
-        let sock = TcpListener::bind(anyaddressthisport)?;
+        let sock = crate::socket::tcp_listen(anyaddressthisport, &self.config)?;
 
-        sock.set_nonblocking(true)?;
-
-        socket.state = SocketState::Bound(sock);
+        socket.set_state(SocketState::Bound(sock))?;
         Ok(())
     }
 
@@ -139,8 +228,49 @@ impl TcpFullStack for crate::Stack {
         socket: &mut TcpSocket,
     ) -> nb::Result<(TcpSocket, SocketAddr), Self::Error> {
         let sock = socket.state.get_bound().map_err(Self::Error::from)?;
-        sock.accept()
-            .map_err(Self::Error::to_nb)
-            .map(|(s, a)| (TcpSocket::connected(s), a))
+        let (s, a) = sock.accept().map_err(Self::Error::to_nb)?;
+        if let Some(nodelay) = self.config.options.tcp_nodelay {
+            s.set_nodelay(nodelay).map_err(Self::Error::from)?;
+        }
+        Ok((TcpSocket::connected(s), a))
+    }
+}
+
+#[cfg(feature = "mio")]
+impl mio::event::Source for TcpSocket {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        self.registration = Some(crate::mio_source::Registration::register(
+            self.as_raw_fd(),
+            registry,
+            token,
+            interests,
+        )?);
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        self.registration = Some(crate::mio_source::Registration::reregister(
+            self.registration.take(),
+            self.as_raw_fd(),
+            registry,
+            token,
+            interests,
+        )?);
+        Ok(())
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        self.registration = None;
+        crate::mio_source::Registration::deregister(self.as_raw_fd(), registry)
     }
 }
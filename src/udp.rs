@@ -7,15 +7,29 @@ use std::net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
 pub struct UdpSocket {
     state: SocketState<net::UdpSocket, net::UdpSocket>,
+    #[cfg(feature = "mio")]
+    registration: Option<crate::mio_source::Registration>,
 }
 
 impl UdpSocket {
     fn new() -> Self {
         Self {
             state: SocketState::new(),
+            #[cfg(feature = "mio")]
+            registration: None,
         }
     }
 
+    /// Replace the socket's state after it has been connected or bound.
+    fn set_state(&mut self, state: SocketState<net::UdpSocket, net::UdpSocket>) -> io::Result<()> {
+        self.state = state;
+        #[cfg(feature = "mio")]
+        if let (Some(registration), Some(fd)) = (&self.registration, self.as_raw_fd()) {
+            registration.follow(fd)?;
+        }
+        Ok(())
+    }
+
     /// Return the raw file descriptor underlying the current socket.
     ///
     /// This is primarily intended for use with `select` style mechanisms: Any of the `nb` methods
@@ -33,6 +47,53 @@ impl UdpSocket {
 
         Some(self.state.get_any().ok()?.as_raw_fd())
     }
+
+    /// Borrow the file descriptor underlying the current socket.
+    ///
+    /// This behaves like [`.as_raw_fd()`](UdpSocket::as_raw_fd), but allows passing the socket to
+    /// libraries that take a [std::os::unix::io::AsFd].
+    #[cfg(any(unix, target_os = "wasi"))]
+    pub fn as_fd(&self) -> Option<std::os::unix::io::BorrowedFd<'_>> {
+        use std::os::unix::io::AsFd;
+
+        Some(self.state.get_any().ok()?.as_fd())
+    }
+
+    /// Take the standard library socket out of the socket.
+    ///
+    /// The socket is returned unmodified (in particular, it stays in non-blocking mode). If the
+    /// socket is neither connected nor bound, there is no standard library socket yet, and the
+    /// original socket is returned as the error.
+    pub fn try_into_std(self) -> Result<net::UdpSocket, Self> {
+        match self.state {
+            SocketState::Connected(s) | SocketState::Bound(s) => Ok(s),
+            SocketState::Building => Err(self),
+        }
+    }
+}
+
+/// Wrap a standard library socket.
+///
+/// The socket is set to non-blocking mode. Depending on whether it is connected, it can be used
+/// with the [UdpClientStack] methods or with the [UdpFullStack] methods.
+///
+/// This is `TryFrom` rather than `From`, as setting the socket to non-blocking mode can fail.
+impl TryFrom<net::UdpSocket> for UdpSocket {
+    type Error = Error;
+
+    fn try_from(sock: net::UdpSocket) -> io::Result<Self> {
+        sock.set_nonblocking(true)?;
+
+        let state = match sock.peer_addr() {
+            Ok(_) => SocketState::Connected(sock),
+            Err(e) if e.kind() == io::ErrorKind::NotConnected => SocketState::Bound(sock),
+            Err(e) => return Err(e),
+        };
+
+        let mut socket = UdpSocket::new();
+        socket.set_state(state)?;
+        Ok(socket)
+    }
 }
 
 impl UdpClientStack for crate::Stack {
@@ -44,18 +105,16 @@ impl UdpClientStack for crate::Stack {
     }
 
     fn connect(&mut self, socket: &mut Self::UdpSocket, remote: SocketAddr) -> std::io::Result<()> {
-        let any = match remote {
+        self.config.check(remote.ip())?;
+
+        let local = self.config.source_address(remote).unwrap_or(match remote {
             SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
             SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
-        };
+        });
 
-        let sock = net::UdpSocket::bind(any)?;
-
-        sock.set_nonblocking(true)?;
+        let sock = crate::socket::udp_connect(local, remote, &self.config)?;
 
-        sock.connect(remote)?;
-        socket.state = SocketState::Connected(sock);
-        Ok(())
+        socket.set_state(SocketState::Connected(sock))
     }
 
     fn send(&mut self, socket: &mut Self::UdpSocket, buffer: &[u8]) -> nb::Result<(), Self::Error> {
@@ -84,14 +143,11 @@ impl UdpClientStack for crate::Stack {
 
 impl UdpFullStack for crate::Stack {
     fn bind(&mut self, socket: &mut UdpSocket, port: u16) -> Result<(), Error> {
-        let anyaddressthisport = net::SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
-
-        let sock = net::UdpSocket::bind(anyaddressthisport)?;
+        let anyaddressthisport = self.config.bind_address(port)?;
 
-        sock.set_nonblocking(true)?;
+        let sock = crate::socket::udp_bind(anyaddressthisport, &self.config)?;
 
-        socket.state = SocketState::Bound(sock);
-        Ok(())
+        socket.set_state(SocketState::Bound(sock))
     }
     fn send_to(
         &mut self,
@@ -99,7 +155,47 @@ impl UdpFullStack for crate::Stack {
         remote: SocketAddr,
         buffer: &[u8],
     ) -> Result<(), nb::Error<Error>> {
+        self.config.check(remote.ip())?;
         let sock = socket.state.get_bound()?;
         sock.send_to(buffer, remote).map(drop).map_err(to_nb)
     }
 }
+
+#[cfg(feature = "mio")]
+impl mio::event::Source for UdpSocket {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> io::Result<()> {
+        self.registration = Some(crate::mio_source::Registration::register(
+            self.as_raw_fd(),
+            registry,
+            token,
+            interests,
+        )?);
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> io::Result<()> {
+        self.registration = Some(crate::mio_source::Registration::reregister(
+            self.registration.take(),
+            self.as_raw_fd(),
+            registry,
+            token,
+            interests,
+        )?);
+        Ok(())
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+        self.registration = None;
+        crate::mio_source::Registration::deregister(self.as_raw_fd(), registry)
+    }
+}
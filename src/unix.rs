@@ -0,0 +1,353 @@
+//! A network stack that runs over Unix domain sockets
+//!
+//! The [UnixStack] implements the same UDP and TCP traits as the [Stack](crate::Stack), but each
+//! socket address is mapped to a path in the file system (by an [AddressMapping]), and the
+//! sockets are Unix domain sockets bound to those paths. This allows testing protocol
+//! implementations hermetically: Tests using different directories can run in parallel without
+//! clashing over ports, and without setting up network namespaces.
+//!
+//! UDP sockets map to datagram sockets, and TCP sockets to stream sockets. Outgoing UDP sockets
+//! are bound to an ephemeral port's path (so that they can receive responses); outgoing stream
+//! sockets are unnamed, and accepting them reports the unspecified address as their peer.
+//!
+//! Socket files created by the stack are removed when the socket is dropped. Stale files (eg.
+//! from a crashed process) are not removed, and make binding to their address fail.
+
+use crate::conversion::to_nb;
+use crate::{SocketState, TcpError};
+use embedded_nal::nb;
+use embedded_nal::{TcpClientStack, TcpFullStack, UdpClientStack, UdpFullStack};
+use std::io::{self, Error, Read, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+
+/// The first port used for outgoing sockets (the start of the IANA dynamic port range)
+const EPHEMERAL_START: u16 = 49152;
+
+/// A bidirectional mapping between socket addresses and Unix socket paths
+pub trait AddressMapping: Send + Sync {
+    /// The path a socket with the given address is bound to.
+    fn path(&self, addr: SocketAddr) -> PathBuf;
+
+    /// The address of a socket bound to the given path.
+    ///
+    /// This returns `None` if the path does not belong to the mapping.
+    fn address(&self, path: &Path) -> Option<SocketAddr>;
+}
+
+/// The default [AddressMapping], which places a socket for each port in a directory
+///
+/// Only the port is used to build the path: The socket for port 1234 is `1234` inside the
+/// directory, whatever the address' IP address is. Paths are mapped back to that port on a
+/// configurable host address, which is `::1` by default.
+#[derive(Clone, Debug)]
+pub struct DirectoryMapping {
+    directory: PathBuf,
+    host: IpAddr,
+}
+
+impl DirectoryMapping {
+    /// Map addresses to socket files in the given directory.
+    ///
+    /// The directory needs to exist and be writable. Bound sockets create their file in it, and
+    /// remove it again when they are dropped (stale files of sockets that were not dropped, eg.
+    /// after a crash, make binding to their port fail).
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            host: Ipv6Addr::LOCALHOST.into(),
+        }
+    }
+
+    /// Set the IP address that is reported for the sockets' paths.
+    pub fn host(mut self, host: IpAddr) -> Self {
+        self.host = host;
+        self
+    }
+}
+
+impl AddressMapping for DirectoryMapping {
+    fn path(&self, addr: SocketAddr) -> PathBuf {
+        self.directory.join(addr.port().to_string())
+    }
+
+    fn address(&self, path: &Path) -> Option<SocketAddr> {
+        if path.parent()? != self.directory {
+            return None;
+        }
+        let port = path.file_name()?.to_str()?.parse().ok()?;
+        Some(SocketAddr::new(self.host, port))
+    }
+}
+
+/// An std::io::Error compatible error type expressing that a peer's socket path does not
+/// correspond to any address in the stack's mapping
+#[derive(Debug)]
+struct Unmapped(PathBuf);
+
+impl std::fmt::Display for Unmapped {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Path {} is not mapped to any address", self.0.display())
+    }
+}
+
+impl std::error::Error for Unmapped {}
+
+/// A network stack that uses Unix domain sockets, implementing ``embedded_nal::UdpFullStack`` and
+/// ``embedded_nal::TcpFullStack``
+///
+/// The stack can be cloned; all clones share the mapping and the allocation of ephemeral ports.
+#[derive(Clone)]
+pub struct UnixStack {
+    mapping: Arc<dyn AddressMapping>,
+    next_ephemeral: Arc<AtomicU16>,
+}
+
+impl UnixStack {
+    /// Create a stack that places its sockets in the given directory, using a
+    /// [DirectoryMapping].
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self::with_mapping(DirectoryMapping::new(directory))
+    }
+
+    /// Create a stack that uses a custom mapping between addresses and paths.
+    pub fn with_mapping(mapping: impl AddressMapping + 'static) -> Self {
+        Self {
+            mapping: Arc::new(mapping),
+            next_ephemeral: Arc::new(AtomicU16::new(EPHEMERAL_START)),
+        }
+    }
+
+    /// The address a peer's socket address stands for
+    fn address(&self, addr: &std::os::unix::net::SocketAddr) -> io::Result<SocketAddr> {
+        match addr.as_pathname() {
+            Some(path) => self
+                .mapping
+                .address(path)
+                .ok_or_else(|| Error::new(io::ErrorKind::InvalidData, Unmapped(path.to_owned()))),
+            None => Ok(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)),
+        }
+    }
+
+    /// Address that `bind` operations use for a port
+    fn bind_address(port: u16) -> SocketAddr {
+        SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port)
+    }
+
+    /// Bind a datagram socket to the next free ephemeral port on the remote address' IP address.
+    fn bind_ephemeral(&self, remote: SocketAddr) -> io::Result<Named<UnixDatagram>> {
+        for _ in EPHEMERAL_START..=u16::MAX {
+            let port = self
+                .next_ephemeral
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |p| {
+                    Some(p.checked_add(1).unwrap_or(EPHEMERAL_START))
+                })
+                .expect("Update function always returns Some");
+            let local = SocketAddr::new(remote.ip(), port);
+            match Named::bind_datagram(self.mapping.path(local)) {
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                result => return result,
+            }
+        }
+        Err(Error::new(
+            io::ErrorKind::AddrInUse,
+            "No ephemeral port available",
+        ))
+    }
+}
+
+/// A socket bound to a path, which is removed when the socket is dropped
+struct Named<S> {
+    socket: S,
+    path: PathBuf,
+}
+
+impl Named<UnixDatagram> {
+    fn bind_datagram(path: PathBuf) -> io::Result<Self> {
+        let socket = UnixDatagram::bind(&path)?;
+        let named = Self { socket, path };
+        named.socket.set_nonblocking(true)?;
+        Ok(named)
+    }
+}
+
+impl Named<UnixListener> {
+    fn bind_listener(path: PathBuf) -> io::Result<Self> {
+        let socket = UnixListener::bind(&path)?;
+        let named = Self { socket, path };
+        named.socket.set_nonblocking(true)?;
+        Ok(named)
+    }
+}
+
+impl<S> Drop for Named<S> {
+    fn drop(&mut self) {
+        // Nothing sensible can be done if this fails; most likely, the file was removed already.
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A UDP-like socket of a [UnixStack], backed by a Unix datagram socket
+pub struct UnixUdpSocket {
+    state: SocketState<Named<UnixDatagram>, Named<UnixDatagram>>,
+}
+
+impl UnixUdpSocket {
+    /// Return the raw file descriptor underlying the current socket.
+    ///
+    /// See [`UdpSocket::as_raw_fd()`](crate::UdpSocket::as_raw_fd) for details.
+    pub fn as_raw_fd(&self) -> Option<RawFd> {
+        Some(self.state.get_any().ok()?.socket.as_raw_fd())
+    }
+}
+
+/// A TCP-like socket of a [UnixStack], backed by a Unix stream socket
+pub struct UnixTcpSocket {
+    state: SocketState<UnixStream, Named<UnixListener>>,
+}
+
+impl UnixTcpSocket {
+    /// Return the raw file descriptor underlying the current socket.
+    ///
+    /// See [`TcpSocket::as_raw_fd()`](crate::TcpSocket::as_raw_fd) for details.
+    pub fn as_raw_fd(&self) -> Option<RawFd> {
+        match &self.state {
+            SocketState::Building => None,
+            SocketState::Connected(s) => Some(s.as_raw_fd()),
+            SocketState::Bound(s) => Some(s.socket.as_raw_fd()),
+        }
+    }
+}
+
+impl UdpClientStack for UnixStack {
+    type UdpSocket = UnixUdpSocket;
+    type Error = Error;
+
+    fn socket(&mut self) -> io::Result<UnixUdpSocket> {
+        Ok(UnixUdpSocket {
+            state: SocketState::new(),
+        })
+    }
+
+    fn connect(&mut self, socket: &mut UnixUdpSocket, remote: SocketAddr) -> io::Result<()> {
+        let sock = self.bind_ephemeral(remote)?;
+        sock.socket.connect(self.mapping.path(remote))?;
+        socket.state = SocketState::Connected(sock);
+        Ok(())
+    }
+
+    fn send(&mut self, socket: &mut UnixUdpSocket, buffer: &[u8]) -> nb::Result<(), Error> {
+        let sock = socket.state.get_running()?;
+        sock.socket.send(buffer).map(drop).map_err(to_nb)
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut UnixUdpSocket,
+        buffer: &mut [u8],
+    ) -> nb::Result<(usize, SocketAddr), Error> {
+        let sock = socket.state.get_any_mut()?;
+        let (len, addr) = sock.socket.recv_from(buffer).map_err(to_nb)?;
+        Ok((len, self.address(&addr)?))
+    }
+
+    fn close(&mut self, _: UnixUdpSocket) -> io::Result<()> {
+        // No-op: Socket gets closed (and its file removed) when it is freed
+        Ok(())
+    }
+}
+
+impl UdpFullStack for UnixStack {
+    fn bind(&mut self, socket: &mut UnixUdpSocket, port: u16) -> io::Result<()> {
+        let sock = Named::bind_datagram(self.mapping.path(Self::bind_address(port)))?;
+        socket.state = SocketState::Bound(sock);
+        Ok(())
+    }
+
+    fn send_to(
+        &mut self,
+        socket: &mut UnixUdpSocket,
+        remote: SocketAddr,
+        buffer: &[u8],
+    ) -> nb::Result<(), Error> {
+        let sock = socket.state.get_bound()?;
+        sock.socket
+            .send_to(buffer, self.mapping.path(remote))
+            .map(drop)
+            .map_err(to_nb)
+    }
+}
+
+impl TcpClientStack for UnixStack {
+    type TcpSocket = UnixTcpSocket;
+    type Error = TcpError;
+
+    fn socket(&mut self) -> Result<UnixTcpSocket, TcpError> {
+        Ok(UnixTcpSocket {
+            state: SocketState::new(),
+        })
+    }
+
+    fn connect(
+        &mut self,
+        socket: &mut UnixTcpSocket,
+        remote: SocketAddr,
+    ) -> nb::Result<(), TcpError> {
+        let stream = UnixStream::connect(self.mapping.path(remote)).map_err(TcpError::from)?;
+        stream.set_nonblocking(true).map_err(TcpError::from)?;
+        socket.state = SocketState::Connected(stream);
+        Ok(())
+    }
+
+    fn send(&mut self, socket: &mut UnixTcpSocket, buffer: &[u8]) -> nb::Result<usize, TcpError> {
+        let stream = socket.state.get_running().map_err(TcpError::from)?;
+        stream.write(buffer).map_err(TcpError::to_nb)
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut UnixTcpSocket,
+        buffer: &mut [u8],
+    ) -> nb::Result<usize, TcpError> {
+        let stream = socket.state.get_running().map_err(TcpError::from)?;
+        stream.read(buffer).map_err(TcpError::to_nb)
+    }
+
+    fn close(&mut self, _: UnixTcpSocket) -> Result<(), TcpError> {
+        // No-op: Socket gets closed (and its file removed) when it is freed
+        Ok(())
+    }
+}
+
+impl TcpFullStack for UnixStack {
+    fn bind(&mut self, socket: &mut UnixTcpSocket, port: u16) -> Result<(), TcpError> {
+        let listener = Named::bind_listener(self.mapping.path(Self::bind_address(port)))?;
+        socket.state = SocketState::Bound(listener);
+        Ok(())
+    }
+
+    fn listen(&mut self, _: &mut UnixTcpSocket) -> Result<(), TcpError> {
+        // Implied in listener creation
+        Ok(())
+    }
+
+    fn accept(
+        &mut self,
+        socket: &mut UnixTcpSocket,
+    ) -> nb::Result<(UnixTcpSocket, SocketAddr), TcpError> {
+        let listener = socket.state.get_bound().map_err(TcpError::from)?;
+        let (stream, addr) = listener.socket.accept().map_err(TcpError::to_nb)?;
+        stream.set_nonblocking(true).map_err(TcpError::from)?;
+        let addr = self.address(&addr).map_err(TcpError::from)?;
+        Ok((
+            UnixTcpSocket {
+                state: SocketState::Connected(stream),
+            },
+            addr,
+        ))
+    }
+}
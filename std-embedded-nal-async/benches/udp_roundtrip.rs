@@ -0,0 +1,88 @@
+//! Compare UDP round trips over loopback between the readiness based stack and the io_uring stack.
+//!
+//! Run with `cargo bench --features io-uring`.
+//!
+//! Each scenario runs a number of client/server pairs concurrently in a single task, so that both
+//! backends run single threaded. With a single pair, there is only ever one operation in flight;
+//! with more pairs, the io_uring backend submits many operations in a single system call.
+//!
+//! The results so far show no clear gain of io_uring, see the `uring` module documentation.
+
+use embedded_nal_async::{ConnectedUdp, UdpStack, UnconnectedUdp};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+const ROUNDS: u32 = 20_000;
+
+/// Send datagrams from a connected client to an unconnected server and back.
+async fn roundtrips(stack: &impl UdpStack, rounds: u32) {
+    let (server_addr, mut server) = stack
+        .bind_single("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let (_, mut client) = stack.connect(server_addr).await.unwrap();
+
+    let data = [0x55; 64];
+    let mut buffer = [0; 1500];
+    for _ in 0..rounds {
+        client.send(&data).await.unwrap();
+        let (len, local, remote) = server.receive_into(&mut buffer).await.unwrap();
+        server.send(local, remote, &buffer[..len]).await.unwrap();
+        client.receive_into(&mut buffer).await.unwrap();
+    }
+}
+
+/// Run a total of ROUNDS round trips spread over `pairs` concurrent client/server pairs, and
+/// report the time taken.
+async fn concurrent(stack: &impl UdpStack, pairs: u32) -> Duration {
+    let mut tasks: Vec<Pin<Box<dyn Future<Output = ()> + '_>>> = (0..pairs)
+        .map(|_| Box::pin(roundtrips(stack, ROUNDS / pairs)) as _)
+        .collect();
+
+    let start = Instant::now();
+    core::future::poll_fn(|cx| {
+        tasks.retain_mut(|task| task.as_mut().poll(cx).is_pending());
+        if tasks.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+    start.elapsed()
+}
+
+/// Number of times each scenario is run; the backends take turns, and the fastest run of each
+/// is reported, as timings on a shared machine are noisy.
+const RUNS: u32 = 5;
+
+fn report(name: &str, pairs: u32, elapsed: Duration) {
+    println!(
+        "{name:>8}, {pairs:>2} pairs: {ROUNDS} round trips in {elapsed:?} ({:?} per round trip)",
+        elapsed / ROUNDS
+    );
+}
+
+fn main() {
+    for pairs in [1, 8, 64] {
+        let mut readiness = Duration::MAX;
+        let mut uring = Duration::MAX;
+        for _ in 0..RUNS {
+            readiness = readiness.min(async_std::task::block_on(concurrent(
+                &std_embedded_nal_async::Stack::default(),
+                pairs,
+            )));
+            uring = uring.min(
+                std_embedded_nal_async::uring::block_on(concurrent(
+                    &std_embedded_nal_async::uring::Stack::default(),
+                    pairs,
+                ))
+                .unwrap(),
+            );
+        }
+        report("std", pairs, readiness);
+        report("io_uring", pairs, uring);
+    }
+}
@@ -0,0 +1,137 @@
+//! Support for systemd-style socket activation
+//!
+//! When started by a service manager that supports the [socket activation protocol], the
+//! listening sockets are created by the service manager, and passed to the application as file
+//! descriptors along with the `LISTEN_PID` and `LISTEN_FDS` environment variables. Rather than
+//! binding sockets through [embedded_nal_async::UdpStack], the application then takes them using
+//! [`listen_fds()`], and turns them into sockets of this crate using [`bind_single()`] or
+//! [`bind_multiple()`].
+//!
+//! The helpers for taking and checking the inherited file descriptors are deliberately duplicated
+//! in the `activation` module of the std-embedded-nal crate, as the crates share no code; changes
+//! to them apply to both.
+//!
+//! [socket activation protocol]: https://www.freedesktop.org/software/systemd/man/sd_listen_fds.html
+
+use crate::{MultiplyBoundSocket, UniquelyBoundSocket};
+use nix::sys::socket::{getsockname, getsockopt, sockopt, AddressFamily, SockType};
+use nix::sys::socket::{SockaddrLike, SockaddrStorage};
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// File descriptor number of the first socket passed in (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// An std::io::Error compatible error type expressing that an inherited socket can not be used
+/// in the requested role
+#[derive(Debug)]
+struct Unsuitable(&'static str);
+
+impl core::fmt::Display for Unsuitable {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Inherited socket is unsuitable: {}", self.0)
+    }
+}
+
+impl std::error::Error for Unsuitable {}
+
+fn unsuitable(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, Unsuitable(reason))
+}
+
+/// Take the file descriptors passed in by the service manager.
+///
+/// If the process was not started through socket activation (or the sockets were already taken
+/// in an earlier call), the result is empty.
+///
+/// Like `sd_listen_fds(1)`, this removes the environment variables, so that the file descriptors
+/// are not claimed twice, and are not announced to child processes. For that reason, it should
+/// be called early in the program, before any threads are started that might access the
+/// environment.
+pub fn listen_fds() -> io::Result<Vec<OwnedFd>> {
+    let pid = std::env::var("LISTEN_PID");
+    let fds = std::env::var("LISTEN_FDS");
+
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let (Ok(pid), Ok(fds)) = (pid, fds) else {
+        return Ok(vec![]);
+    };
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(vec![]);
+    }
+    let fds: RawFd = fds.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "LISTEN_FDS is not a number of file descriptors",
+        )
+    })?;
+
+    (LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(fds))
+        .map(|fd| {
+            nix::fcntl::fcntl(
+                fd,
+                nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC),
+            )?;
+            // The service manager passed this file descriptor for our exclusive use, and the
+            // environment variables are gone, so nothing else takes ownership of it.
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        })
+        .collect()
+}
+
+/// Check that the file descriptor is an IP socket of the given type.
+fn check_socket(fd: &OwnedFd, expected: SockType) -> io::Result<()> {
+    if getsockopt(fd, sockopt::SockType).map_err(|_| unsuitable("not a socket"))? != expected {
+        return Err(unsuitable(match expected {
+            SockType::Datagram => "not a datagram socket",
+            _ => "not a stream socket",
+        }));
+    }
+
+    let local: SockaddrStorage = getsockname(fd.as_raw_fd())?;
+    match local.family() {
+        Some(AddressFamily::Inet) | Some(AddressFamily::Inet6) => Ok(()),
+        _ => Err(unsuitable("not an IP socket")),
+    }
+}
+
+/// Check that the file descriptor is a bound but unconnected UDP socket, and turn it into one,
+/// along with whether it is a dual-stack socket whose addresses are given out in V4-mapped mode.
+fn udp_socket(fd: OwnedFd) -> io::Result<(std::net::UdpSocket, bool)> {
+    check_socket(&fd, SockType::Datagram)?;
+
+    let socket = std::net::UdpSocket::from(fd);
+    match socket.peer_addr() {
+        Err(e) if e.kind() == io::ErrorKind::NotConnected => (),
+        Err(e) => return Err(e),
+        Ok(_) => return Err(unsuitable("connected")),
+    }
+    let v4_mapped = crate::pmtu::families(&socket)? == (true, true);
+    Ok((socket, v4_mapped))
+}
+
+/// Turn an inherited file descriptor into a socket as created by
+/// [embedded_nal_async::UdpStack::bind_single].
+///
+/// If the socket is an IPv6 socket that also handles IPv4 traffic (`IPV6_V6ONLY` is off, as it is
+/// by default for systemd's `ListenDatagram=[::]:…`), it reports and accepts IPv4 addresses as
+/// such, like sockets of a stack in [V4-mapped mode](crate::StackBuilder::v4_mapped) do.
+pub fn bind_single(
+    fd: OwnedFd,
+) -> io::Result<(embedded_nal_async::SocketAddr, UniquelyBoundSocket)> {
+    let (socket, v4_mapped) = udp_socket(fd)?;
+    UniquelyBoundSocket::new(socket, v4_mapped)
+}
+
+/// Turn an inherited file descriptor into a socket as created by
+/// [embedded_nal_async::UdpStack::bind_multiple].
+///
+/// This enables the reception of packet information on the socket, which the service manager
+/// does not usually do. Dual-stack sockets are handled like in [`bind_single()`].
+pub fn bind_multiple(fd: OwnedFd) -> io::Result<MultiplyBoundSocket> {
+    let (socket, v4_mapped) = udp_socket(fd)?;
+    MultiplyBoundSocket::new(socket, v4_mapped)
+}
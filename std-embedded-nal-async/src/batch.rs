@@ -0,0 +1,233 @@
+//! Batched sending and receiving of datagrams through `sendmmsg` and `recvmmsg`
+//!
+//! The batch operations wait for the socket just like their single counterparts, but then move as
+//! many datagrams as are available (when receiving) or as the socket accepts (when sending) in a
+//! single system call.
+//!
+//! The headers passed to the system calls contain raw pointers, and are only built right before
+//! each call, so that the futures stay `Send`.
+
+use crate::metadata::{self, Metadata, ReceiveSpace};
+use crate::udp;
+use crate::{ConnectedSocket, MultiplyBoundSocket, UniquelyBoundSocket};
+use nix::libc;
+use nix::sys::socket::SockaddrStorage;
+use std::io::Error;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Receive into the given headers, returning how many datagrams were received.
+fn recvmmsg(fd: RawFd, headers: &mut [libc::mmsghdr]) -> Result<usize, Error> {
+    // SAFETY: All headers point to buffers that are valid for writes of the given lengths for the
+    // duration of the call.
+    let count = unsafe {
+        libc::recvmmsg(
+            fd,
+            headers.as_mut_ptr(),
+            headers.len() as _,
+            libc::MSG_TRUNC as _,
+            core::ptr::null_mut(),
+        )
+    };
+    match count {
+        -1 => Err(Error::last_os_error()),
+        count => Ok(count as usize),
+    }
+}
+
+/// Send the given headers, returning how many datagrams were sent.
+fn sendmmsg(fd: RawFd, headers: &mut [libc::mmsghdr]) -> Result<usize, Error> {
+    // SAFETY: All headers point to data that is valid for the duration of the call, and the
+    // kernel only writes to the headers' msg_len.
+    let count = unsafe { libc::sendmmsg(fd, headers.as_mut_ptr(), headers.len() as _, 0) };
+    match count {
+        -1 => Err(Error::last_os_error()),
+        count => Ok(count as usize),
+    }
+}
+
+/// Send all datagrams, waiting for the socket as often as needed.
+///
+/// `header` builds the header for the datagram with the given index, whose data is already
+/// described in the given iovec.
+async fn send_all(
+    socket: &async_io::Async<std::net::UdpSocket>,
+    data: &[&[u8]],
+    header: impl Fn(usize, &mut libc::iovec) -> libc::msghdr,
+) -> Result<(), Error> {
+    let mut done = 0;
+    while done < data.len() {
+        done += socket
+            .write_with(|s| {
+                let mut iovs: Vec<_> = data[done..].iter().map(|d| metadata::iovec(d)).collect();
+                let mut headers: Vec<_> = iovs
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(i, iov)| mmsghdr(header(done + i, iov)))
+                    .collect();
+                let count = sendmmsg(s.as_raw_fd(), &mut headers)?;
+                for (header, data) in headers[..count].iter().zip(&data[done..]) {
+                    udp::check_sent(header.msg_len as usize, data)?;
+                }
+                Ok(count)
+            })
+            .await
+            .map_err(|e| crate::pmtu::classify(e, socket))?;
+    }
+    Ok(())
+}
+
+fn mmsghdr(msg_hdr: libc::msghdr) -> libc::mmsghdr {
+    libc::mmsghdr {
+        msg_hdr,
+        msg_len: 0,
+    }
+}
+
+fn iovec_mut(buffer: &mut [u8]) -> libc::iovec {
+    libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut _,
+        iov_len: buffer.len(),
+    }
+}
+
+/// Build a header that only carries data, as used on connected sockets.
+///
+/// The header points into `iov`, and must not be used after it is gone.
+fn data_header(iov: &mut libc::iovec) -> libc::msghdr {
+    // SAFETY: msghdr is plain old data, for which all zeros is a valid value.
+    let mut msg: libc::msghdr = unsafe { core::mem::zeroed() };
+    msg.msg_iov = iov;
+    msg.msg_iovlen = 1;
+    msg
+}
+
+impl ConnectedSocket {
+    /// Receive up to one datagram per buffer, and report the length of each received datagram.
+    ///
+    /// This waits until at least one datagram is available. Like with
+    /// [`receive_into()`](embedded_nal_async::ConnectedUdp::receive_into), the full length of
+    /// each datagram is reported even if it exceeded its buffer.
+    pub async fn receive_batch(&mut self, buffers: &mut [&mut [u8]]) -> Result<Vec<usize>, Error> {
+        self.socket
+            .read_with(|s| {
+                let mut iovs: Vec<_> = buffers.iter_mut().map(|b| iovec_mut(b)).collect();
+                let mut headers: Vec<_> = iovs
+                    .iter_mut()
+                    .map(|iov| mmsghdr(data_header(iov)))
+                    .collect();
+                let count = recvmmsg(s.as_raw_fd(), &mut headers)?;
+                Ok(headers[..count]
+                    .iter()
+                    .map(|header| header.msg_len as usize)
+                    .collect())
+            })
+            .await
+    }
+
+    /// Send all given datagrams.
+    ///
+    /// As many datagrams as the socket accepts are sent at once; this only waits if the socket
+    /// does not accept all of them.
+    pub async fn send_batch(&mut self, datagrams: &[&[u8]]) -> Result<(), Error> {
+        send_all(&self.socket, datagrams, |_, iov| data_header(iov)).await
+    }
+}
+
+macro_rules! impl_batch {
+    ($socket:ident) => {
+        impl $socket {
+            /// Receive up to one datagram per buffer, and report the length, local address and
+            /// remote address of each received datagram.
+            ///
+            /// This waits until at least one datagram is available. Like with
+            /// [`receive_into()`](embedded_nal_async::UnconnectedUdp::receive_into), the full
+            /// length of each datagram is reported even if it exceeded its buffer.
+            pub async fn receive_batch(
+                &mut self,
+                buffers: &mut [&mut [u8]],
+            ) -> Result<
+                Vec<(
+                    usize,
+                    embedded_nal_async::SocketAddr,
+                    embedded_nal_async::SocketAddr,
+                )>,
+                Error,
+            > {
+                let mut spaces: Vec<_> = buffers.iter().map(|_| ReceiveSpace::new()).collect();
+                let received = self
+                    .socket
+                    .read_with(|s| {
+                        let mut iovs: Vec<_> = buffers.iter_mut().map(|b| iovec_mut(b)).collect();
+                        let mut headers: Vec<_> = spaces
+                            .iter_mut()
+                            .zip(iovs.iter_mut())
+                            .map(|(space, iov)| mmsghdr(space.header(iov)))
+                            .collect();
+                        let count = recvmmsg(s.as_raw_fd(), &mut headers)?;
+                        Ok(headers[..count]
+                            .iter()
+                            .zip(&spaces)
+                            .map(|(header, space)| {
+                                // SAFETY: The receive call succeeded for the first `count` headers.
+                                unsafe { space.parse(&header.msg_hdr, header.msg_len as usize) }
+                            })
+                            .collect::<Vec<_>>())
+                    })
+                    .await?;
+
+                received
+                    .into_iter()
+                    .map(|mut received| {
+                        let (local, remote) = udp::addresses(
+                            &mut received,
+                            &mut self.interfaces,
+                            self.bound_address,
+                        )?;
+                        Ok((
+                            received.length,
+                            udp::to_api(local, self.v4_mapped),
+                            udp::to_api(remote, self.v4_mapped),
+                        ))
+                    })
+                    .collect()
+            }
+
+            /// Send all given datagrams, each from its local address to its remote address.
+            ///
+            /// The addresses are checked like in [`send()`](embedded_nal_async::UnconnectedUdp::send)
+            /// before anything is sent. As many datagrams as the socket accepts are sent at once;
+            /// this only waits if the socket does not accept all of them.
+            pub async fn send_batch(
+                &mut self,
+                datagrams: &[(
+                    embedded_nal_async::SocketAddr,
+                    embedded_nal_async::SocketAddr,
+                    &[u8],
+                )],
+            ) -> Result<(), Error> {
+                let mut prepared = Vec::with_capacity(datagrams.len());
+                for (local, remote, _) in datagrams {
+                    let remote = udp::to_socket(*remote, self.v4_mapped);
+                    let control = udp::send_control(
+                        self.bound_address,
+                        &self.interfaces,
+                        udp::to_socket(*local, self.v4_mapped),
+                        remote,
+                        &Metadata::default(),
+                    )?;
+                    prepared.push((SockaddrStorage::from(remote), control.unwrap_or_default()));
+                }
+                let data: Vec<_> = datagrams.iter().map(|(_, _, data)| *data).collect();
+
+                send_all(&self.socket, &data, |i, iov| {
+                    let (remote, control) = &prepared[i];
+                    metadata::send_header(iov, Some(remote), control)
+                })
+                .await
+            }
+        }
+    };
+}
+
+impl_batch!(UniquelyBoundSocket);
+impl_batch!(MultiplyBoundSocket);
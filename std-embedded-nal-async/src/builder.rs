@@ -0,0 +1,249 @@
+//! Configuration of a [Stack]
+
+use crate::Stack;
+
+/// Everything a [Stack] can be configured with through a [StackBuilder]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Config {
+    pub(crate) v4_mapped: bool,
+    pub(crate) socket_options: SocketOptions,
+    pub(crate) bind_device: Option<std::ffi::OsString>,
+    pub(crate) socket_hook: Option<SocketHook>,
+}
+
+/// What a socket created by a [Stack] is used for, as reported to its
+/// [socket hook](StackBuilder::socket_hook)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SocketRole {
+    /// A UDP socket that is about to be bound and connected, for a
+    /// [`ConnectedSocket`](crate::ConnectedSocket)
+    UdpConnected,
+    /// A UDP socket that is about to be bound, for a
+    /// [`UniquelyBoundSocket`](crate::UniquelyBoundSocket)
+    UdpUniquelyBound,
+    /// A UDP socket that is about to be bound, for a
+    /// [`MultiplyBoundSocket`](crate::MultiplyBoundSocket)
+    UdpMultiplyBound,
+    /// A TCP socket that is about to connect, for a [`TcpConnection`](crate::TcpConnection)
+    TcpConnection,
+}
+
+/// Callback run on every socket a [Stack] creates, see [StackBuilder::socket_hook]
+#[derive(Clone)]
+pub(crate) struct SocketHook(pub(crate) std::sync::Arc<HookFn>);
+
+type HookFn =
+    dyn Fn(std::os::unix::io::BorrowedFd<'_>, SocketRole) -> std::io::Result<()> + Send + Sync;
+
+impl core::fmt::Debug for SocketHook {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("SocketHook")
+    }
+}
+
+/// Options applied to every UDP socket a [Stack] creates
+///
+/// The options are set right after the socket is created, before it is bound, so that options
+/// that only take effect at binding (like address and port reuse) work on all kinds of sockets.
+/// Options not set here keep the operating system's defaults.
+///
+/// Sockets with different options are created through different stacks; stacks are cheap to
+/// create.
+///
+/// ```
+/// use std_embedded_nal_async::SocketOptions;
+///
+/// // Several processes serving the CoAP port
+/// let stack = std_embedded_nal_async::Stack::builder()
+///     .socket_options(SocketOptions::default().reuse_address(true).reuse_port(true))
+///     .build();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    pub(crate) reuse_address: Option<bool>,
+    pub(crate) reuse_port: Option<bool>,
+    pub(crate) receive_buffer_size: Option<usize>,
+    pub(crate) send_buffer_size: Option<usize>,
+    pub(crate) hop_limit: Option<u8>,
+    pub(crate) dscp: Option<u8>,
+    pub(crate) receive_metadata: Option<bool>,
+    pub(crate) receive_timestamps: Option<bool>,
+    pub(crate) receive_offload: Option<bool>,
+}
+
+impl SocketOptions {
+    /// Allow binding to an address that is still in use by sockets that are being closed
+    /// (`SO_REUSEADDR`), or (on Linux) that other sockets with this option are bound to.
+    pub fn reuse_address(mut self, reuse_address: bool) -> Self {
+        self.reuse_address = Some(reuse_address);
+        self
+    }
+
+    /// Allow several sockets to bind to the same address and port, if all of them set this
+    /// (`SO_REUSEPORT`). Incoming unicast datagrams are then distributed among them.
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = Some(reuse_port);
+        self
+    }
+
+    /// Request a size for the socket's receive buffer (`SO_RCVBUF`).
+    ///
+    /// The operating system may adjust the size; Linux doubles it to account for its bookkeeping,
+    /// and caps it at a system-wide maximum.
+    pub fn receive_buffer_size(mut self, size: usize) -> Self {
+        self.receive_buffer_size = Some(size);
+        self
+    }
+
+    /// Request a size for the socket's send buffer (`SO_SNDBUF`), which is adjusted like the
+    /// [receive buffer size](Self::receive_buffer_size).
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the hop limit (IPv4: time to live) of unicast datagrams sent from the socket
+    /// (`IP_TTL` / `IPV6_UNICAST_HOPS`).
+    ///
+    /// Individual datagrams can still override it through
+    /// [`Metadata::hop_limit`](crate::Metadata::hop_limit).
+    pub fn hop_limit(mut self, hop_limit: u8) -> Self {
+        self.hop_limit = Some(hop_limit);
+        self
+    }
+
+    /// Set the DiffServ codepoint of datagrams sent from the socket, which makes up the upper six
+    /// bits of the traffic class (`IP_TOS` / `IPV6_TCLASS`); the ECN bits are left at zero.
+    ///
+    /// Only the lower six bits of `dscp` are used. Individual datagrams can still override the
+    /// traffic class through [`Metadata::traffic_class`](crate::Metadata::traffic_class).
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.dscp = Some(dscp & 0x3f);
+        self
+    }
+
+    /// Report the traffic class and hop limit of received datagrams, which are then available
+    /// through the sockets' `receive_into_with_metadata()` methods (see
+    /// [`Metadata`](crate::Metadata)).
+    ///
+    /// As this is enabled before binding, every datagram the socket receives carries them.
+    pub fn receive_metadata(mut self, receive_metadata: bool) -> Self {
+        self.receive_metadata = Some(receive_metadata);
+        self
+    }
+
+    /// Take kernel timestamps of received datagrams (`SO_TIMESTAMPNS`), which are then available
+    /// through the sockets' `receive_into_with_timestamp()` methods.
+    ///
+    /// As with [metadata](Self::receive_metadata), every datagram the socket receives carries one.
+    pub fn receive_timestamps(mut self, receive_timestamps: bool) -> Self {
+        self.receive_timestamps = Some(receive_timestamps);
+        self
+    }
+
+    /// Have the kernel coalesce received datagrams (generic receive offload, `UDP_GRO`), where it
+    /// supports that.
+    ///
+    /// This is for sockets that only receive through `receive_coalesced()`, as all receive
+    /// operations then get coalesced datagrams; see
+    /// [`UniquelyBoundSocket::enable_receive_offload()`](crate::UniquelyBoundSocket::enable_receive_offload).
+    pub fn receive_offload(mut self, receive_offload: bool) -> Self {
+        self.receive_offload = Some(receive_offload);
+        self
+    }
+}
+
+/// Builder for a [Stack] that deviates from the default behavior
+///
+/// A builder is obtained from [Stack::builder]; all settings not altered on the builder keep the
+/// behavior of [Stack::default].
+///
+/// ```
+/// let stack = std_embedded_nal_async::Stack::builder()
+///     .v4_mapped(true)
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct StackBuilder {
+    config: Config,
+}
+
+impl StackBuilder {
+    pub(crate) fn new() -> Self {
+        Self {
+            config: Config::default(),
+        }
+    }
+
+    /// Use IPv6 sockets for IPv4 traffic as well, through V4-mapped addresses.
+    ///
+    /// With this enabled, UDP sockets are always IPv6 sockets that accept IPv4 traffic, so a
+    /// socket bound to `[::]:port` serves IPv4 and IPv6 peers alike. IPv4 addresses are still used
+    /// at the API: They are converted to V4-mapped addresses when passed in, and back when given
+    /// out.
+    ///
+    /// This requires an operating system that supports V4-mapped addresses (eg. Linux, but not
+    /// FreeBSD in its default configuration). It is disabled by default, in which case IPv4
+    /// addresses are used with IPv4 sockets.
+    pub fn v4_mapped(mut self, v4_mapped: bool) -> Self {
+        self.config.v4_mapped = v4_mapped;
+        self
+    }
+
+    /// Set options on every UDP socket the stack creates.
+    ///
+    /// Replaces any options set before.
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.config.socket_options = socket_options;
+        self
+    }
+
+    /// Pin every UDP and TCP socket the stack creates to the named network interface, eg.
+    /// `"eth0"` (`SO_BINDTODEVICE`).
+    ///
+    /// Traffic then only leaves through that interface, and only datagrams that arrive on it are
+    /// received, independently of what the routing table would pick. Setting this needs the
+    /// `CAP_NET_RAW` capability on kernels before 5.7; creating sockets fails if the interface
+    /// does not exist.
+    pub fn bind_device(mut self, interface: impl Into<std::ffi::OsString>) -> Self {
+        self.config.bind_device = Some(interface.into());
+        self
+    }
+
+    /// Run a callback on every socket the stack creates, after the configured
+    /// [socket options](Self::socket_options) are applied but before the socket is bound or
+    /// connected.
+    ///
+    /// The callback receives the socket's file descriptor and what it is going to be used for.
+    /// It can apply further configuration (eg. set a firewall mark or attach a BPF filter) or log
+    /// the creation. An error returned from the callback fails the operation that creates the
+    /// socket.
+    ///
+    /// ```
+    /// let stack = std_embedded_nal_async::Stack::builder()
+    ///     .socket_hook(|fd, role| {
+    ///         use std::os::unix::io::AsRawFd;
+    ///         eprintln!("Created {role:?} socket {}", fd.as_raw_fd());
+    ///         Ok(())
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn socket_hook(
+        mut self,
+        hook: impl Fn(std::os::unix::io::BorrowedFd<'_>, SocketRole) -> std::io::Result<()>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.config.socket_hook = Some(SocketHook(std::sync::Arc::new(hook)));
+        self
+    }
+
+    /// Create a [Stack] with the configured settings.
+    pub fn build(self) -> Stack {
+        Stack {
+            config: self.config,
+        }
+    }
+}
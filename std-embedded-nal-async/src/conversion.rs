@@ -17,54 +17,141 @@ impl From<embedded_nal_async::IpAddr> for IpAddr {
     }
 }
 
-// That this is missing zone info on IPv6 probably just makes std::net::IpAddr a bad intermediate
-// type.
+impl From<net::IpAddr> for IpAddr {
+    fn from(input: net::IpAddr) -> Self {
+        Self(input)
+    }
+}
+
 impl From<nix::libc::in6_pktinfo> for IpAddr {
     fn from(input: nix::libc::in6_pktinfo) -> Self {
-        // FIXME why isn't this having zone infos??
         Self(input.ipi6_addr.s6_addr.into())
     }
 }
 
-impl From<IpAddr> for nix::libc::in6_pktinfo {
-    fn from(input: IpAddr) -> nix::libc::in6_pktinfo {
+/// Whether an IPv6 address is only meaningful together with a zone (interface) index
+fn needs_zone(addr: net::Ipv6Addr) -> bool {
+    let segments = addr.segments();
+    // Unicast link-local (fe80::/10) or multicast with interface- or link-local scope
+    (segments[0] & 0xffc0) == 0xfe80
+        || (addr.is_multicast() && matches!(segments[0] & 0x000f, 0x1 | 0x2))
+}
+
+impl SocketAddr {
+    /// Express an IPv4 address as a V4-mapped IPv6 address, as used on sockets in V4-mapped mode.
+    pub(crate) fn mapped(self) -> Self {
+        match self.0 {
+            net::SocketAddr::V4(a) => {
+                Self(net::SocketAddrV6::new(a.ip().to_ipv6_mapped(), a.port(), 0, 0).into())
+            }
+            _ => self,
+        }
+    }
+
+    /// Express a V4-mapped IPv6 address as the IPv4 address it stands for.
+    pub(crate) fn unmapped(self) -> Self {
+        match self.0 {
+            net::SocketAddr::V6(a) => match a.ip().to_ipv4_mapped() {
+                Some(ip) => Self(net::SocketAddrV4::new(ip, a.port()).into()),
+                None => self,
+            },
+            _ => self,
+        }
+    }
+
+    /// Build the local address of a received datagram from its packet info.
+    ///
+    /// The interface index is carried as the address' scope ID where the address needs a zone to
+    /// be meaningful, so that responses sent from that address go out on the same interface.
+    pub(crate) fn from_pktinfo6(input: nix::libc::in6_pktinfo, port: u16) -> Self {
+        let addr = net::Ipv6Addr::from(input.ipi6_addr.s6_addr);
+        let scope_id = if needs_zone(addr) {
+            input.ipi6_ifindex
+        } else {
+            0
+        };
+        Self(net::SocketAddrV6::new(addr, port, 0, scope_id).into())
+    }
+}
+
+/// An std::io::Error compatible error type expressing that an address of one family was used
+/// where the other family was required (eg. when sending from an IPv6 address to an IPv4 address)
+#[derive(Debug)]
+struct FamilyMismatch;
+
+impl core::fmt::Display for FamilyMismatch {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Address families of the involved addresses differ")
+    }
+}
+
+impl std::error::Error for FamilyMismatch {}
+
+fn family_mismatch() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, FamilyMismatch)
+}
+
+impl TryFrom<SocketAddr> for nix::libc::in6_pktinfo {
+    type Error = io::Error;
+
+    fn try_from(input: SocketAddr) -> io::Result<nix::libc::in6_pktinfo> {
         let input = match input.0 {
-            std::net::IpAddr::V6(a) => a,
-            _ => panic!("Type requires IPv6 addresses"),
+            std::net::SocketAddr::V6(a) => a,
+            _ => return Err(family_mismatch()),
         };
-        nix::libc::in6_pktinfo {
+        // Datagrams can not be sent from a multicast address; responses to a group are sent from
+        // the interface's address instead.
+        let source = match input.ip().is_multicast() {
+            true => net::Ipv6Addr::UNSPECIFIED,
+            false => *input.ip(),
+        };
+        Ok(nix::libc::in6_pktinfo {
             ipi6_addr: nix::libc::in6_addr {
-                s6_addr: input.octets(),
+                s6_addr: source.octets(),
             },
-            // FIXME and here it really hurts
-            ipi6_ifindex: 0,
-        }
+            ipi6_ifindex: input.scope_id(),
+        })
     }
 }
 
 impl From<nix::libc::in_pktinfo> for IpAddr {
+    /// Extract the local address of a received datagram.
+    ///
+    /// That is the local address the system would use to respond, except for multicast, where it
+    /// is the group the datagram was sent to.
     fn from(input: nix::libc::in_pktinfo) -> Self {
-        // FIXME discarding interface index?
-        Self(net::Ipv4Addr::from(input.ipi_spec_dst.s_addr).into())
+        // s_addr is in network byte order
+        let destination = net::Ipv4Addr::from(u32::from_be(input.ipi_addr.s_addr));
+        if destination.is_multicast() {
+            return Self(destination.into());
+        }
+        Self(net::Ipv4Addr::from(u32::from_be(input.ipi_spec_dst.s_addr)).into())
     }
 }
 
-impl From<IpAddr> for nix::libc::in_pktinfo {
-    fn from(input: IpAddr) -> nix::libc::in_pktinfo {
+impl TryFrom<IpAddr> for nix::libc::in_pktinfo {
+    type Error = io::Error;
+
+    /// Build packet info for sending from the given address.
+    ///
+    /// The interface index is left unset; IPv4 addresses can not carry it, so it is up to the
+    /// caller to set it. Like with IPv6, multicast addresses leave the source address to the
+    /// system.
+    fn try_from(input: IpAddr) -> io::Result<nix::libc::in_pktinfo> {
         let input = match input.0 {
+            std::net::IpAddr::V4(a) if a.is_multicast() => net::Ipv4Addr::UNSPECIFIED,
             std::net::IpAddr::V4(a) => a,
-            _ => panic!("Type requires IPv4 addresses"),
+            _ => return Err(family_mismatch()),
         };
-        nix::libc::in_pktinfo {
+        Ok(nix::libc::in_pktinfo {
             ipi_spec_dst: nix::libc::in_addr {
-                s_addr: input.into(),
+                s_addr: u32::from(input).to_be(),
             },
             ipi_addr: nix::libc::in_addr {
-                s_addr: input.into(),
+                s_addr: u32::from(input).to_be(),
             },
-            // FIXME and here it really hurts
             ipi_ifindex: 0,
-        }
+        })
     }
 }
 
@@ -109,13 +196,37 @@ impl From<SocketAddr> for net::SocketAddr {
 
 impl From<embedded_nal_async::SocketAddr> for SocketAddr {
     fn from(input: embedded_nal_async::SocketAddr) -> Self {
-        Self((IpAddr::from(input.ip()).0, input.port()).into())
+        match input {
+            embedded_nal_async::SocketAddr::V4(a) => {
+                Self(net::SocketAddrV4::new(a.ip().octets().into(), a.port()).into())
+            }
+            embedded_nal_async::SocketAddr::V6(a) => Self(
+                net::SocketAddrV6::new(
+                    a.ip().octets().into(),
+                    a.port(),
+                    a.flowinfo(),
+                    a.scope_id(),
+                )
+                .into(),
+            ),
+        }
     }
 }
 
 impl From<SocketAddr> for embedded_nal_async::SocketAddr {
     fn from(s: SocketAddr) -> embedded_nal_async::SocketAddr {
-        (IpAddr(s.0.ip()), s.0.port()).into()
+        match s.0 {
+            net::SocketAddr::V4(a) => {
+                embedded_nal_async::SocketAddrV4::new(a.ip().octets().into(), a.port()).into()
+            }
+            net::SocketAddr::V6(a) => embedded_nal_async::SocketAddrV6::new(
+                a.ip().octets().into(),
+                a.port(),
+                a.flowinfo(),
+                a.scope_id(),
+            )
+            .into(),
+        }
     }
 }
 
@@ -147,6 +258,18 @@ mod test {
         assert_eq!(native, converted_to_native);
     }
 
+    #[test]
+    fn pktinfo_byte_order() {
+        let native: std::net::IpAddr = "127.0.0.1".parse().unwrap();
+
+        let info: nix::libc::in_pktinfo = IpAddr(native).try_into().unwrap();
+        // The address is stored in network byte order
+        assert_eq!(info.ipi_spec_dst.s_addr.to_ne_bytes(), [127, 0, 0, 1]);
+
+        let converted_to_native: std::net::IpAddr = IpAddr::from(info).into();
+        assert_eq!(native, converted_to_native);
+    }
+
     #[test]
     fn equal_port() {
         let nal: embedded_nal_async::SocketAddr = "[2001:db8::17]:42".parse().unwrap();
@@ -158,4 +281,59 @@ mod test {
         let converted_to_native: std::net::SocketAddr = SocketAddr::from(nal).into();
         assert_eq!(native, converted_to_native);
     }
+
+    #[test]
+    fn equal_scope() {
+        let nal: embedded_nal_async::SocketAddr =
+            embedded_nal_async::SocketAddrV6::new("fe80::1".parse().unwrap(), 42, 0, 3).into();
+        let native: std::net::SocketAddr = "[fe80::1%3]:42".parse().unwrap();
+
+        let converted_to_nal: embedded_nal_async::SocketAddr = SocketAddr(native).into();
+        assert_eq!(nal, converted_to_nal);
+
+        let converted_to_native: std::net::SocketAddr = SocketAddr::from(nal).into();
+        assert_eq!(native, converted_to_native);
+    }
+
+    #[test]
+    fn pktinfo_scope() {
+        let pktinfo = |addr: &str, ifindex| nix::libc::in6_pktinfo {
+            ipi6_addr: nix::libc::in6_addr {
+                s6_addr: addr.parse::<net::Ipv6Addr>().unwrap().octets(),
+            },
+            ipi6_ifindex: ifindex,
+        };
+
+        let local = SocketAddr::from_pktinfo6(pktinfo("fe80::1", 3), 42);
+        assert_eq!(local.0, "[fe80::1%3]:42".parse().unwrap());
+        let back = nix::libc::in6_pktinfo::try_from(local).unwrap();
+        assert_eq!(back.ipi6_ifindex, 3);
+
+        // Global addresses do not need the zone
+        let local = SocketAddr::from_pktinfo6(pktinfo("2001:db8::1", 3), 42);
+        assert_eq!(local.0, "[2001:db8::1]:42".parse().unwrap());
+    }
+
+    #[test]
+    fn v4_mapping() {
+        let v4 = SocketAddr("192.0.2.1:42".parse().unwrap());
+        let mapped = v4.mapped();
+        assert_eq!(mapped.0, "[::ffff:192.0.2.1]:42".parse().unwrap());
+        assert_eq!(mapped.unmapped().0, v4.0);
+
+        let v6 = SocketAddr("[2001:db8::1]:42".parse().unwrap());
+        assert_eq!(v6.mapped().0, v6.0);
+        assert_eq!(v6.unmapped().0, v6.0);
+    }
+
+    #[test]
+    fn pktinfo_family_mismatch() {
+        let v4 = SocketAddr("192.0.2.1:42".parse().unwrap());
+        let err = nix::libc::in6_pktinfo::try_from(v4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let v6 = IpAddr("2001:db8::1".parse().unwrap());
+        let err = nix::libc::in_pktinfo::try_from(v6).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
 }
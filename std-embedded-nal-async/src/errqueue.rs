@@ -0,0 +1,186 @@
+//! ICMP errors on UDP sockets, read from the socket's error queue
+//!
+//! With `IP_RECVERR` / `IPV6_RECVERR` enabled, the operating system queues an entry for every ICMP
+//! error that a datagram sent from the socket caused, carrying the datagram's original destination
+//! and the details of the ICMP message.
+//!
+//! Enabling this also makes the operating system report errors on unconnected sockets (rather than
+//! only on connected ones): The next operation on the socket fails with the error's kind (eg.
+//! [`ConnectionRefused`](std::io::ErrorKind::ConnectionRefused)), which is the cue to read the
+//! details from the queue.
+
+use crate::metadata::ReceiveSpace;
+use crate::pmtu::families;
+use crate::socket::set_raw_option;
+use crate::udp;
+use crate::{ConnectedSocket, MultiplyBoundSocket, UniquelyBoundSocket};
+use nix::libc;
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags};
+use nix::sys::socket::SockaddrStorage;
+use std::io::Error;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+
+/// An ICMP error caused by a datagram sent from a socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IcmpError {
+    /// Destination of the datagram that caused the error
+    pub destination: embedded_nal_async::SocketAddr,
+    /// ICMP (for IPv4) or ICMPv6 (for IPv6) message type, eg. 3 (IPv4) or 1 (IPv6) for
+    /// "destination unreachable"
+    pub icmp_type: u8,
+    /// Code of the ICMP message, eg. 3 (IPv4) or 4 (IPv6) for "port unreachable"
+    pub icmp_code: u8,
+    /// Address of the node that sent the ICMP message, if known
+    pub reporter: Option<embedded_nal_async::IpAddr>,
+}
+
+fn enable(socket: &impl AsRawFd) -> Result<(), Error> {
+    let (v4, v6) = families(socket)?;
+    let on: libc::c_int = 1;
+    if v4 {
+        set_raw_option(socket, libc::IPPROTO_IP, libc::IP_RECVERR, &on)?;
+    }
+    if v6 {
+        set_raw_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, &on)?;
+    }
+    Ok(())
+}
+
+/// Read one entry from the socket's error queue, failing with `WouldBlock` if it is empty.
+///
+/// Entries that do not stem from ICMP messages (eg. local errors) are skipped, and produce
+/// `Ok(None)`.
+fn recv_error(fd: RawFd, v4_mapped: bool) -> Result<Option<IcmpError>, Error> {
+    let mut space = ReceiveSpace::new();
+    // The original datagram is not of interest
+    let mut iov = libc::iovec {
+        iov_base: core::ptr::null_mut(),
+        iov_len: 0,
+    };
+    let mut msg = space.header(&mut iov);
+
+    // SAFETY: All pointers in msg are valid for writes of the given lengths for the duration of
+    // the call.
+    let length = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_ERRQUEUE) };
+    if length == -1 {
+        return Err(Error::last_os_error());
+    }
+    // SAFETY: The receive call succeeded.
+    let mut received = unsafe { space.parse(&msg, length as usize) };
+
+    let Some((error, offender)) = received.extended_error.take() else {
+        return Ok(None);
+    };
+    if error.ee_origin != libc::SO_EE_ORIGIN_ICMP && error.ee_origin != libc::SO_EE_ORIGIN_ICMP6 {
+        return Ok(None);
+    }
+    let destination = udp::remote_address(received.remote.take())?;
+    Ok(Some(IcmpError {
+        destination: udp::to_api(destination, v4_mapped),
+        icmp_type: error.ee_type,
+        icmp_code: error.ee_code,
+        reporter: offender.and_then(|offender| reporter(offender, v4_mapped)),
+    }))
+}
+
+fn reporter(offender: SockaddrStorage, v4_mapped: bool) -> Option<embedded_nal_async::IpAddr> {
+    let ip = match (offender.as_sockaddr_in(), offender.as_sockaddr_in6()) {
+        (Some(sin), _) => std::net::Ipv4Addr::from(sin.ip()).into(),
+        (_, Some(sin6)) => sin6.ip().into(),
+        // The kernel leaves the family unspecified when it does not know the reporter
+        _ => return None,
+    };
+    Some(udp::to_api(std::net::SocketAddr::new(ip, 0), v4_mapped).ip())
+}
+
+/// Create an epoll instance that becomes readable when the socket has an error pending, but not
+/// when it has data to receive.
+fn error_waiter(socket: &std::net::UdpSocket) -> Result<async_io::Async<OwnedFd>, Error> {
+    let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?;
+    // The error condition is reported without being asked for
+    epoll.add(socket, EpollEvent::new(EpollFlags::empty(), 0))?;
+    async_io::Async::new(epoll.0)
+}
+
+/// Wait for an ICMP error on the socket.
+///
+/// The socket's own readiness does not tell errors from received data, so this waits on an epoll
+/// instance that only watches for the socket's error condition.
+async fn receive(
+    socket: &async_io::Async<std::net::UdpSocket>,
+    v4_mapped: bool,
+) -> Result<IcmpError, Error> {
+    let mut waiter = None;
+    let mut woken = false;
+    loop {
+        match recv_error(socket.as_raw_fd(), v4_mapped) {
+            Ok(Some(error)) => return Ok(error),
+            Ok(None) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+        }
+        if woken {
+            // The error condition without a queued entry is an error that was not queued; it is
+            // taken here, as it would otherwise keep the condition up.
+            if let Some(e) = socket.get_ref().take_error()? {
+                return Err(e);
+            }
+        }
+        let waiter = match &mut waiter {
+            Some(waiter) => waiter,
+            None => waiter.insert(error_waiter(socket.get_ref())?),
+        };
+        waiter.readable().await?;
+        woken = true;
+    }
+}
+
+impl ConnectedSocket {
+    /// Start queuing ICMP errors for [`.receive_icmp_error()`](Self::receive_icmp_error)
+    /// (`IP_RECVERR` / `IPV6_RECVERR`).
+    ///
+    /// Only errors caused by datagrams sent after this was called are queued.
+    pub fn enable_icmp_errors(&self) -> Result<(), Error> {
+        enable(&self.socket)
+    }
+
+    /// Wait for an ICMP error caused by a datagram sent from this socket, once enabled through
+    /// [`.enable_icmp_errors()`](Self::enable_icmp_errors).
+    ///
+    /// This waits for errors only; datagrams waiting to be received do not wake it up.
+    pub async fn receive_icmp_error(&mut self) -> Result<IcmpError, Error> {
+        receive(&self.socket, self.v4_mapped).await
+    }
+}
+
+macro_rules! impl_errqueue {
+    ($socket:ident) => {
+        impl $socket {
+            /// Start queuing ICMP errors for
+            #[doc = concat!("[`.receive_icmp_error()`](", stringify!($socket), "::receive_icmp_error)")]
+            /// (`IP_RECVERR` / `IPV6_RECVERR`).
+            ///
+            /// Only errors caused by datagrams sent after this was called are queued. Once
+            /// enabled, the operating system also reports errors on the unconnected socket: The
+            /// next receive or send operation fails with the error's kind (eg.
+            /// [`ConnectionRefused`](std::io::ErrorKind::ConnectionRefused)), after which the
+            /// details can be read from the queue.
+            pub fn enable_icmp_errors(&self) -> Result<(), Error> {
+                enable(&self.socket)
+            }
+
+            /// Wait for an ICMP error caused by a datagram sent from this socket, once enabled
+            /// through
+            #[doc = concat!("[`.enable_icmp_errors()`](", stringify!($socket), "::enable_icmp_errors).")]
+            ///
+            /// This waits for errors only; datagrams waiting to be received do not wake it up.
+            pub async fn receive_icmp_error(&mut self) -> Result<IcmpError, Error> {
+                receive(&self.socket, self.v4_mapped).await
+            }
+        }
+    };
+}
+
+impl_errqueue!(UniquelyBoundSocket);
+impl_errqueue!(MultiplyBoundSocket);
@@ -0,0 +1,86 @@
+//! Enumeration of the host's network interfaces and their addresses
+//!
+//! This helps picking the local address to send from on a [`MultiplyBoundSocket`], or the
+//! interface to join a multicast group on. Interface indices are the operating system's, and thus
+//! the same that are reported as scope IDs of link-local addresses of received datagrams.
+//!
+//! The listing is deliberately duplicated in the `interfaces` module of the std-embedded-nal
+//! crate, as the crates share no code; it only differs in the address type, and in that this
+//! crate's stack has no family policy to filter addresses by.
+//!
+//! [`MultiplyBoundSocket`]: crate::MultiplyBoundSocket
+
+use crate::conversion;
+use crate::Stack;
+use nix::ifaddrs::getifaddrs;
+use nix::net::if_::{if_nametoindex, InterfaceFlags};
+use std::io::Error;
+use std::net::SocketAddrV4;
+
+/// A network interface of the host, as reported by [`Stack::interfaces()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Interface {
+    /// Name of the interface, eg. `"eth0"`
+    pub name: String,
+    /// Index of the interface
+    pub index: u32,
+    /// Whether the interface is administratively up (`IFF_UP`)
+    pub up: bool,
+    /// Whether the interface is a loopback interface (`IFF_LOOPBACK`)
+    pub loopback: bool,
+    /// Whether the interface supports multicast (`IFF_MULTICAST`)
+    pub multicast: bool,
+    /// IP addresses assigned to the interface
+    ///
+    /// Link-local IPv6 addresses are only usable along with the interface's index as a scope ID.
+    pub addresses: Vec<embedded_nal_async::IpAddr>,
+}
+
+impl Stack {
+    /// List the host's network interfaces along with their IP addresses (Linux only).
+    ///
+    /// Interfaces are listed even if they are down or have no addresses. Addresses of both
+    /// families are listed: Unlike that of std-embedded-nal, this stack has no family policy that
+    /// could exclude one, and V4-mapped mode does not change which addresses are usable.
+    pub fn interfaces(&self) -> Result<Vec<Interface>, Error> {
+        let mut interfaces: Vec<Interface> = vec![];
+        for entry in getifaddrs()? {
+            // IPv4 addresses with a label are reported under "name:label"; the colon is not valid
+            // in interface names.
+            let name = match entry.interface_name.split_once(':') {
+                Some((name, _)) => name,
+                None => &entry.interface_name,
+            };
+            let interface = match interfaces.iter().position(|i| i.name == name) {
+                Some(position) => &mut interfaces[position],
+                None => {
+                    interfaces.push(Interface {
+                        name: name.to_string(),
+                        index: if_nametoindex(name)?,
+                        up: entry.flags.contains(InterfaceFlags::IFF_UP),
+                        loopback: entry.flags.contains(InterfaceFlags::IFF_LOOPBACK),
+                        multicast: entry.flags.contains(InterfaceFlags::IFF_MULTICAST),
+                        addresses: vec![],
+                    });
+                    interfaces.last_mut().expect("Just pushed")
+                }
+            };
+
+            let address: Option<std::net::IpAddr> = match entry.address {
+                Some(address) => match (address.as_sockaddr_in(), address.as_sockaddr_in6()) {
+                    (Some(sin), _) => Some((*SocketAddrV4::from(*sin).ip()).into()),
+                    (_, Some(sin6)) => Some(sin6.ip().into()),
+                    _ => None,
+                },
+                None => None,
+            };
+            if let Some(address) = address {
+                interface
+                    .addresses
+                    .push(conversion::IpAddr::from(address).into());
+            }
+        }
+        Ok(interfaces)
+    }
+}
@@ -5,6 +5,12 @@
 //!
 //! All implementations use `std::io::Error` as their error type.
 //!
+//! Sockets passed in by a service manager (like systemd's socket activation) can be taken using
+//! the [activation] module.
+//!
+//! On Linux, the `io-uring` feature enables an alternative backend in the `uring` module, which
+//! performs socket operations through io_uring and brings its own executor.
+//!
 //! [embedded-nal-async]: https://crates.io/crates/embedded-nal-async
 //!
 //! # Caveats
@@ -17,12 +23,6 @@
 //! UniquelyBound version works without that restriction, but so far there has not been a need to
 //! run this on non-POSIX systems.
 //!
-//! ## UDP uniquely bound: excessive lengths
-//!
-//! Received messages whose length exceeds the provided buffer are not yet reported correctly for
-//! UDP's uniquely bound sockets. (They work as they shoudl for the multiply bound sockets, which
-//! depend on `recvmsg`, which allows detecting the overly long messages).
-//!
 //! ## Excessive lifetimes of receive buffers
 //!
 //! As required by the [embedded_nal_async] APIs, buffers are provided through exclusive references
@@ -32,18 +32,50 @@
 //! that uses up the buffers before it waits again) this makes an acutal difference when running on
 //! full link time optimization.
 
+pub mod activation;
+mod batch;
+mod builder;
 mod conversion;
 mod dns;
+mod errqueue;
+mod interfaces;
+mod metadata;
+mod multicast;
+mod offload;
+mod pmtu;
+mod socket;
 mod tcp;
+mod timestamp;
 mod udp;
+#[cfg(feature = "io-uring")]
+pub mod uring;
+
+pub use builder::{SocketOptions, SocketRole, StackBuilder};
+pub use errqueue::IcmpError;
+pub use interfaces::Interface;
+pub use metadata::{Ecn, Metadata};
+pub use offload::Segments;
+pub use pmtu::{MessageTooLarge, PathMtuDiscovery};
+pub use tcp::TcpConnection;
+pub use udp::{ConnectedSocket, MultiplyBoundSocket, UniquelyBoundSocket};
 
 /// The operating system's network stack, implementing ``embedded_nal_async::UdpStack``.
 ///
-/// The user may instantiate a stack using the `Stack::default()` function.
+/// The user may instantiate a stack using the `Stack::default()` function, or configure one
+/// using [`Stack::builder()`].
 ///
 /// The stack can be cloned, as it is not a resource that needs any synchronization. This is not
 /// made implicit as Copy, though (although there's not technical reason not to). That is to alert
 /// users to the difficulties that'd arise when copying around a stack rather than using it through
 /// some mechanism of synchronization.
 #[derive(Clone, Default)]
-pub struct Stack;
+pub struct Stack {
+    config: builder::Config,
+}
+
+impl Stack {
+    /// Start configuring a stack that deviates from the defaults.
+    pub fn builder() -> StackBuilder {
+        StackBuilder::new()
+    }
+}
@@ -0,0 +1,567 @@
+//! IP header information of datagrams beyond their addresses: the traffic class (which carries the
+//! ECN codepoint) and the hop limit
+//!
+//! nix does not wrap the control messages carrying these, so `recvmsg` and `sendmsg` are called
+//! through `nix::libc` directly here, along with the packet info and receive timestamps that go
+//! with them.
+
+use crate::pmtu::families;
+use crate::socket::{get_raw_option, set_raw_option};
+use crate::udp;
+use crate::{ConnectedSocket, MultiplyBoundSocket, UniquelyBoundSocket};
+use nix::libc;
+use nix::sys::socket::{ControlMessageOwned, SockaddrLike, SockaddrStorage};
+use std::io::Error;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Explicit Congestion Notification codepoint, carried in the two lowest bits of the traffic class
+/// ([RFC 3168](https://www.rfc-editor.org/rfc/rfc3168))
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecn {
+    /// Not ECN-capable transport
+    NotEct,
+    /// ECN-capable transport, ECT(1)
+    Ect1,
+    /// ECN-capable transport, ECT(0)
+    Ect0,
+    /// Congestion experienced
+    Ce,
+}
+
+impl Ecn {
+    const MASK: u8 = 0b11;
+
+    fn from_bits(bits: u8) -> Self {
+        match bits & Self::MASK {
+            0b00 => Ecn::NotEct,
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Ecn::NotEct => 0b00,
+            Ecn::Ect1 => 0b01,
+            Ecn::Ect0 => 0b10,
+            Ecn::Ce => 0b11,
+        }
+    }
+}
+
+/// IP header information of a datagram beyond its addresses
+///
+/// When receiving, fields are `None` if the operating system did not report them. When sending,
+/// fields that are `None` are left at the socket's defaults.
+///
+/// Reporting received metadata needs to be enabled on a socket, either for all sockets of a stack
+/// through [`SocketOptions::receive_metadata`](crate::SocketOptions::receive_metadata) (which
+/// enables it before the socket is bound, so that every datagram carries it), or on an existing
+/// socket through its `enable_metadata()` method (after which datagrams that were queued before
+/// still lack it). Kernel timestamps are enabled alike, through
+/// [`SocketOptions::receive_timestamps`](crate::SocketOptions::receive_timestamps) or
+/// `enable_timestamps()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The traffic class (IPv4: type of service) byte, consisting of the DSCP and the ECN bits
+    pub traffic_class: Option<u8>,
+    /// The hop limit (IPv4: time to live)
+    pub hop_limit: Option<u8>,
+}
+
+impl Metadata {
+    /// The ECN codepoint of the traffic class, if known
+    pub fn ecn(&self) -> Option<Ecn> {
+        self.traffic_class.map(Ecn::from_bits)
+    }
+
+    /// Set the ECN codepoint of the traffic class, leaving its DSCP bits in place (or at zero if
+    /// no traffic class was set).
+    pub fn set_ecn(&mut self, ecn: Ecn) {
+        let dscp = self.traffic_class.unwrap_or(0) & !Ecn::MASK;
+        self.traffic_class = Some(dscp | ecn.bits());
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Add the control messages that set these fields on a datagram sent to `remote`.
+    ///
+    /// Datagrams to IPv4 (including V4-mapped) addresses take the IPv4 options even on IPv6
+    /// sockets.
+    fn push_to(&self, control: &mut Control, remote: std::net::SocketAddr) {
+        let v4 = match remote {
+            std::net::SocketAddr::V4(_) => true,
+            std::net::SocketAddr::V6(a) => a.ip().to_ipv4_mapped().is_some(),
+        };
+        if let Some(traffic_class) = self.traffic_class {
+            let traffic_class = libc::c_int::from(traffic_class);
+            match v4 {
+                true => control.push(libc::IPPROTO_IP, libc::IP_TOS, traffic_class),
+                false => control.push(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, traffic_class),
+            }
+        }
+        if let Some(hop_limit) = self.hop_limit {
+            let hop_limit = libc::c_int::from(hop_limit);
+            match v4 {
+                true => control.push(libc::IPPROTO_IP, libc::IP_TTL, hop_limit),
+                false => control.push(libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT, hop_limit),
+            }
+        }
+    }
+}
+
+/// Make the operating system report metadata on a socket.
+///
+/// On sockets that handle IPv4 traffic (including IPv6 sockets that do through V4-mapped
+/// addresses), the IPv4 options are enabled, as they govern IPv4 traffic.
+pub(crate) fn enable(socket: &impl AsRawFd) -> Result<(), Error> {
+    let (v4, v6) = families(socket)?;
+    let on: libc::c_int = 1;
+    if v4 {
+        set_raw_option(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, &on)?;
+        set_raw_option(socket, libc::IPPROTO_IP, libc::IP_RECVTTL, &on)?;
+    }
+    if v6 {
+        set_raw_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, &on)?;
+        set_raw_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, &on)?;
+    }
+    Ok(())
+}
+
+/// Find whether metadata reporting is enabled on a socket, however it was enabled.
+pub(crate) fn enabled(socket: &impl AsRawFd) -> Result<bool, Error> {
+    let on: libc::c_int = match families(socket)? {
+        (_, true) => get_raw_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT)?,
+        _ => get_raw_option(socket, libc::IPPROTO_IP, libc::IP_RECVTTL)?,
+    };
+    Ok(on != 0)
+}
+
+/// An std::io::Error compatible error type expressing that information was requested from a
+/// socket that was not set up to report it
+#[derive(Debug)]
+pub(crate) struct NotEnabled(pub(crate) &'static str);
+
+impl core::fmt::Display for NotEnabled {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Reporting {} is not enabled on the socket", self.0)
+    }
+}
+
+impl std::error::Error for NotEnabled {}
+
+/// Fail unless reporting the named information was enabled.
+pub(crate) fn check_enabled(enabled: bool, what: &'static str) -> Result<(), Error> {
+    match enabled {
+        true => Ok(()),
+        false => Err(Error::new(
+            std::io::ErrorKind::InvalidInput,
+            NotEnabled(what),
+        )),
+    }
+}
+
+/// Buffer for control messages to be sent along with a datagram
+///
+/// It is sized for the packet info, both metadata fields and a segment size.
+#[derive(Default)]
+pub(crate) struct Control {
+    // u64 for the alignment of cmsghdr
+    buffer: [u64; 16],
+    len: usize,
+}
+
+impl Control {
+    pub(crate) fn push<T: Copy>(&mut self, level: libc::c_int, kind: libc::c_int, value: T) {
+        let size = core::mem::size_of::<T>() as libc::c_uint;
+        // SAFETY: CMSG_SPACE is a pure calculation.
+        let space = unsafe { libc::CMSG_SPACE(size) } as usize;
+        assert!(
+            self.len + space <= core::mem::size_of_val(&self.buffer),
+            "Control buffer is sized for all messages that are sent"
+        );
+        // SAFETY: The header and its data fit in the buffer as checked above, the offset keeps
+        // the header aligned as all previous messages were padded to CMSG_SPACE, and the data is
+        // written unaligned.
+        unsafe {
+            let header = (self.buffer.as_mut_ptr() as *mut u8).add(self.len) as *mut libc::cmsghdr;
+            (*header).cmsg_len = libc::CMSG_LEN(size) as _;
+            (*header).cmsg_level = level;
+            (*header).cmsg_type = kind;
+            (libc::CMSG_DATA(header) as *mut T).write_unaligned(value);
+        }
+        self.len += space;
+    }
+}
+
+/// Build the header for sending the data in `iov` to `remote` (or, on a connected socket, to its
+/// peer) with the given control messages.
+///
+/// The header points into its arguments, and must not be used after they are gone.
+pub(crate) fn send_header(
+    iov: &mut libc::iovec,
+    remote: Option<&SockaddrStorage>,
+    control: &Control,
+) -> libc::msghdr {
+    // SAFETY: msghdr is plain old data, for which all zeros is a valid value.
+    let mut msg: libc::msghdr = unsafe { core::mem::zeroed() };
+    if let Some(remote) = remote {
+        msg.msg_name = remote.as_ptr() as *mut _;
+        msg.msg_namelen = remote.len();
+    }
+    msg.msg_iov = iov;
+    msg.msg_iovlen = 1;
+    if control.len > 0 {
+        msg.msg_control = control.buffer.as_ptr() as *mut _;
+        msg.msg_controllen = control.len as _;
+    }
+    msg
+}
+
+/// Describe a buffer to the operating system.
+pub(crate) fn iovec(data: &[u8]) -> libc::iovec {
+    libc::iovec {
+        // Not written through when sending
+        iov_base: data.as_ptr() as *mut _,
+        iov_len: data.len(),
+    }
+}
+
+/// Send a datagram with the given control messages (to the peer of a connected socket if no
+/// `remote` is given), and report how much of it was sent.
+pub(crate) fn sendmsg(
+    fd: RawFd,
+    data: &[u8],
+    remote: Option<std::net::SocketAddr>,
+    control: &Control,
+) -> Result<usize, Error> {
+    let remote = remote.map(SockaddrStorage::from);
+    let mut iov = iovec(data);
+    let msg = send_header(&mut iov, remote.as_ref(), control);
+    // SAFETY: All pointers in msg are valid for the duration of the call, and the kernel does not
+    // write through them.
+    let sent = unsafe { libc::sendmsg(fd, &msg, 0) };
+    match sent {
+        -1 => Err(Error::last_os_error()),
+        sent => Ok(sent as usize),
+    }
+}
+
+/// A datagram received through [`recvmsg`] or [`ReceiveSpace`]
+pub(crate) struct Received {
+    /// Full length of the datagram, even if it was truncated
+    pub(crate) length: usize,
+    pub(crate) remote: Option<SockaddrStorage>,
+    /// Packet info control message, expressed like nix would
+    pub(crate) packet_info: Option<ControlMessageOwned>,
+    pub(crate) metadata: Metadata,
+    /// Kernel receive timestamp, if enabled through `SO_TIMESTAMPNS`
+    pub(crate) timestamp: Option<std::time::SystemTime>,
+    /// Size of the individual datagrams, if several were coalesced through `UDP_GRO`
+    pub(crate) segment_size: Option<usize>,
+    /// Extended error and the address of its offender, if read from the error queue
+    pub(crate) extended_error: Option<(libc::sock_extended_err, Option<SockaddrStorage>)>,
+}
+
+/// Room for the remote address and the control messages of a received datagram
+pub(crate) struct ReceiveSpace {
+    name: libc::sockaddr_storage,
+    // Room for packet info, all metadata in either family, a timestamp and a segment size (or an
+    // extended error); u64 for the alignment of cmsghdr
+    control: [u64; 32],
+}
+
+impl ReceiveSpace {
+    pub(crate) fn new() -> Self {
+        Self {
+            // SAFETY: sockaddr_storage is plain old data, for which all zeros is a valid value.
+            name: unsafe { core::mem::zeroed() },
+            control: [0; 32],
+        }
+    }
+
+    /// Build the header for receiving into `iov` and this space.
+    ///
+    /// The header points into its arguments, and must not be used after they are gone.
+    pub(crate) fn header(&mut self, iov: &mut libc::iovec) -> libc::msghdr {
+        // SAFETY: msghdr is plain old data, for which all zeros is a valid value.
+        let mut msg: libc::msghdr = unsafe { core::mem::zeroed() };
+        msg.msg_name = &mut self.name as *mut _ as *mut _;
+        msg.msg_namelen = core::mem::size_of_val(&self.name) as _;
+        msg.msg_iov = iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = self.control.as_mut_ptr() as *mut _;
+        msg.msg_controllen = core::mem::size_of_val(&self.control) as _;
+        msg
+    }
+
+    /// Evaluate a header built by [`.header()`](Self::header) after the kernel received a
+    /// datagram of the given length into it.
+    ///
+    /// # Safety
+    ///
+    /// The header must have been filled in by a successful receive call.
+    pub(crate) unsafe fn parse(&self, msg: &libc::msghdr, length: usize) -> Received {
+        // SAFETY: The kernel wrote an address of the reported length.
+        let remote = unsafe {
+            SockaddrStorage::from_raw(
+                &self.name as *const _ as *const libc::sockaddr,
+                Some(msg.msg_namelen),
+            )
+        };
+        let mut received = Received {
+            length,
+            remote,
+            packet_info: None,
+            metadata: Metadata::default(),
+            timestamp: None,
+            segment_size: None,
+            extended_error: None,
+        };
+
+        // SAFETY: The kernel set up msg_control and msg_controllen to contain well-formed control
+        // messages, whose data is read unaligned in the type the kernel documents for each.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+            while !cmsg.is_null() {
+                let data = libc::CMSG_DATA(cmsg);
+                let int = || (data as *const libc::c_int).read_unaligned() as u8;
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                        let pi = (data as *const libc::in_pktinfo).read_unaligned();
+                        received.packet_info = Some(ControlMessageOwned::Ipv4PacketInfo(pi));
+                    }
+                    (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                        let pi = (data as *const libc::in6_pktinfo).read_unaligned();
+                        received.packet_info = Some(ControlMessageOwned::Ipv6PacketInfo(pi));
+                    }
+                    // Unlike the others, this is a single byte
+                    (libc::IPPROTO_IP, libc::IP_TOS) => {
+                        received.metadata.traffic_class = Some(*data)
+                    }
+                    (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                        received.metadata.traffic_class = Some(int())
+                    }
+                    (libc::IPPROTO_IP, libc::IP_TTL)
+                    | (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                        received.metadata.hop_limit = Some(int())
+                    }
+                    (libc::SOL_UDP, libc::UDP_GRO) => {
+                        let size = (data as *const libc::c_int).read_unaligned();
+                        received.segment_size = usize::try_from(size).ok();
+                    }
+                    (libc::IPPROTO_IP, libc::IP_RECVERR)
+                    | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR) => {
+                        let ee = data as *const libc::sock_extended_err;
+                        let offender =
+                            SockaddrStorage::from_raw(libc::SO_EE_OFFENDER(ee) as *const _, None);
+                        received.extended_error = Some((ee.read_unaligned(), offender));
+                    }
+                    (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                        let ts = (data as *const libc::timespec).read_unaligned();
+                        // Timestamps before the epoch are not expected from the kernel's realtime
+                        // clock, and would not be meaningful receive times
+                        received.timestamp = u64::try_from(ts.tv_sec).ok().map(|secs| {
+                            std::time::UNIX_EPOCH
+                                + std::time::Duration::new(secs, ts.tv_nsec as u32)
+                        });
+                    }
+                    _ => (),
+                }
+                cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+            }
+        }
+
+        received
+    }
+}
+
+/// Receive a datagram along with its packet info, metadata and timestamp.
+pub(crate) fn recvmsg(fd: RawFd, buffer: &mut [u8]) -> Result<Received, Error> {
+    let mut space = ReceiveSpace::new();
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut _,
+        iov_len: buffer.len(),
+    };
+    let mut msg = space.header(&mut iov);
+
+    // SAFETY: All pointers in msg are valid for writes of the given lengths for the duration of
+    // the call.
+    let length = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_TRUNC) };
+    if length == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    // SAFETY: The receive call succeeded.
+    Ok(unsafe { space.parse(&msg, length as usize) })
+}
+
+macro_rules! impl_metadata {
+    ($socket:ident) => {
+        impl $socket {
+            /// Enable reporting the metadata of received datagrams on the socket.
+            ///
+            /// Datagrams that were queued on the socket before lack it; see [`Metadata`] for how to
+            /// enable it before the socket is bound.
+            pub fn enable_metadata(&mut self) -> Result<(), Error> {
+                enable(self.socket.get_ref())?;
+                self.metadata_enabled = true;
+                Ok(())
+            }
+
+            /// Receive a datagram like
+            /// [`receive_into()`](embedded_nal_async::UnconnectedUdp::receive_into), and also report
+            /// its traffic class (including the ECN codepoint) and hop limit.
+            ///
+            /// This fails with an [`InvalidInput`](std::io::ErrorKind::InvalidInput) error unless
+            /// reporting metadata was enabled on the socket, see [`Metadata`].
+            pub async fn receive_into_with_metadata(
+                &mut self,
+                buffer: &mut [u8],
+            ) -> Result<
+                (
+                    usize,
+                    embedded_nal_async::SocketAddr,
+                    embedded_nal_async::SocketAddr,
+                    Metadata,
+                ),
+                Error,
+            > {
+                check_enabled(self.metadata_enabled, "metadata")?;
+                let (local, remote, received) = udp::receive_with_control(
+                    &self.socket,
+                    &mut self.interfaces,
+                    buffer,
+                    self.bound_address,
+                )
+                .await?;
+                Ok((
+                    received.length,
+                    udp::to_api(local, self.v4_mapped),
+                    udp::to_api(remote, self.v4_mapped),
+                    received.metadata,
+                ))
+            }
+
+            /// Send a datagram like [`send()`](embedded_nal_async::UnconnectedUdp::send), setting the
+            /// fields of the metadata that are given.
+            pub async fn send_with_metadata(
+                &mut self,
+                local: embedded_nal_async::SocketAddr,
+                remote: embedded_nal_async::SocketAddr,
+                data: &[u8],
+                metadata: &Metadata,
+            ) -> Result<(), Error> {
+                udp::send_from(
+                    &self.socket,
+                    self.bound_address,
+                    &self.interfaces,
+                    udp::to_socket(local, self.v4_mapped),
+                    udp::to_socket(remote, self.v4_mapped),
+                    metadata,
+                    data,
+                )
+                .await
+            }
+        }
+    };
+}
+
+impl_metadata!(UniquelyBoundSocket);
+impl_metadata!(MultiplyBoundSocket);
+
+impl ConnectedSocket {
+    /// Enable reporting the metadata of received datagrams on the socket, like
+    /// [`UniquelyBoundSocket::enable_metadata()`] does.
+    pub fn enable_metadata(&mut self) -> Result<(), Error> {
+        enable(self.socket.get_ref())?;
+        self.metadata_enabled = true;
+        Ok(())
+    }
+
+    /// Receive a datagram like [`receive_into()`](embedded_nal_async::ConnectedUdp::receive_into),
+    /// and also report its traffic class (including the ECN codepoint) and hop limit.
+    ///
+    /// This fails with an [`InvalidInput`](std::io::ErrorKind::InvalidInput) error unless
+    /// reporting metadata was enabled on the socket, see [`Metadata`].
+    pub async fn receive_into_with_metadata(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Metadata), Error> {
+        check_enabled(self.metadata_enabled, "metadata")?;
+        let received = self
+            .socket
+            .read_with(|s| recvmsg(s.as_raw_fd(), buffer))
+            .await?;
+        Ok((received.length, received.metadata))
+    }
+
+    /// Send a datagram like [`send()`](embedded_nal_async::ConnectedUdp::send), setting the fields
+    /// of the metadata that are given.
+    pub async fn send_with_metadata(
+        &mut self,
+        data: &[u8],
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
+        let mut control = Control::default();
+        if !metadata.is_empty() {
+            // The options to set depend on the family of the peer
+            metadata.push_to(&mut control, self.socket.get_ref().peer_addr()?);
+        }
+        self.socket
+            .write_with(|s| {
+                let sent_len = sendmsg(s.as_raw_fd(), data, None, &control)?;
+                udp::check_sent(sent_len, data)
+            })
+            .await
+            .map_err(|e| crate::pmtu::classify(e, &self.socket))
+    }
+}
+
+/// Assemble the control messages for sending a datagram to `remote`: packet info for `local` (if
+/// given) and the metadata. Returns `None` if there are none.
+pub(crate) fn control(
+    local_pktinfo: Option<PacketInfo>,
+    remote: std::net::SocketAddr,
+    metadata: &Metadata,
+) -> Option<Control> {
+    if local_pktinfo.is_none() && metadata.is_empty() {
+        return None;
+    }
+    let mut control = Control::default();
+    match local_pktinfo {
+        Some(PacketInfo::V4(pi)) => control.push(libc::IPPROTO_IP, libc::IP_PKTINFO, pi),
+        Some(PacketInfo::V6(pi)) => control.push(libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, pi),
+        None => (),
+    }
+    metadata.push_to(&mut control, remote);
+    Some(control)
+}
+
+/// Packet info selecting the local address of a sent datagram
+pub(crate) enum PacketInfo {
+    V4(libc::in_pktinfo),
+    V6(libc::in6_pktinfo),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ecn_bits() {
+        let mut metadata = Metadata::default();
+        assert_eq!(metadata.ecn(), None);
+        metadata.set_ecn(Ecn::Ce);
+        assert_eq!(metadata.traffic_class, Some(0b11));
+
+        // DSCP bits are preserved
+        metadata.traffic_class = Some(0xb8);
+        metadata.set_ecn(Ecn::Ect1);
+        assert_eq!(metadata.traffic_class, Some(0xb9));
+        assert_eq!(metadata.ecn(), Some(Ecn::Ect1));
+    }
+}
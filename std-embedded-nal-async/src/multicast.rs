@@ -0,0 +1,257 @@
+//! Multicast group membership and options for unconnected UDP sockets
+//!
+//! The socket options are set as raw options, as the protocol independent `MCAST_*` options are
+//! not wrapped by nix.
+//!
+//! Group memberships are requested at the option level of the group's family. That way, IPv4
+//! groups can be joined on V4-mapped IPv6 sockets, on which Linux forwards IPv4 level options to
+//! the IPv4 implementation.
+
+use crate::conversion;
+use crate::socket::set_raw_option;
+use crate::{MultiplyBoundSocket, UniquelyBoundSocket};
+use nix::libc;
+use std::io::Error;
+use std::net::IpAddr;
+use std::os::unix::io::AsRawFd;
+
+/// Express an IP address as a `sockaddr_storage` without a port, as used in group requests.
+fn storage(addr: IpAddr) -> libc::sockaddr_storage {
+    // SAFETY: sockaddr_storage is plain old data, for which all zeros is a valid value.
+    let mut storage: libc::sockaddr_storage = unsafe { core::mem::zeroed() };
+    match addr {
+        IpAddr::V4(addr) => {
+            let sin = libc::sockaddr_in {
+                sin_family: libc::AF_INET as _,
+                sin_port: 0,
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from(addr).to_be(),
+                },
+                sin_zero: [0; 8],
+            };
+            // SAFETY: sockaddr_storage is large and aligned enough to hold any sockaddr.
+            unsafe { (&mut storage as *mut _ as *mut libc::sockaddr_in).write(sin) };
+        }
+        IpAddr::V6(addr) => {
+            // SAFETY: sockaddr_in6 is plain old data, for which all zeros is a valid value.
+            let mut sin6: libc::sockaddr_in6 = unsafe { core::mem::zeroed() };
+            sin6.sin6_family = libc::AF_INET6 as _;
+            sin6.sin6_addr.s6_addr = addr.octets();
+            // SAFETY: sockaddr_storage is large and aligned enough to hold any sockaddr.
+            unsafe { (&mut storage as *mut _ as *mut libc::sockaddr_in6).write(sin6) };
+        }
+    }
+    storage
+}
+
+/// The socket option level for requests about the given group
+fn level(group: IpAddr) -> libc::c_int {
+    match group {
+        IpAddr::V4(_) => libc::IPPROTO_IP,
+        IpAddr::V6(_) => libc::IPPROTO_IPV6,
+    }
+}
+
+/// Convert a group or source address given at the API into the form passed in group requests.
+///
+/// V4-mapped addresses are always unmapped, as the operating system expects IPv4 groups as
+/// `AF_INET` addresses even on IPv6 sockets.
+fn to_request(addr: embedded_nal_async::IpAddr) -> IpAddr {
+    let addr: IpAddr = conversion::IpAddr::from(addr).into();
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+        addr => addr,
+    }
+}
+
+fn membership(
+    socket: &impl AsRawFd,
+    name: libc::c_int,
+    group: embedded_nal_async::IpAddr,
+    interface: u32,
+) -> Result<(), Error> {
+    let group = to_request(group);
+    let request = libc::group_req {
+        gr_interface: interface,
+        gr_group: storage(group),
+    };
+    set_raw_option(socket, level(group), name, &request)
+}
+
+fn source_membership(
+    socket: &impl AsRawFd,
+    name: libc::c_int,
+    group: embedded_nal_async::IpAddr,
+    source: embedded_nal_async::IpAddr,
+    interface: u32,
+) -> Result<(), Error> {
+    let group = to_request(group);
+    let request = libc::group_source_req {
+        gsr_interface: interface,
+        gsr_group: storage(group),
+        gsr_source: storage(to_request(source)),
+    };
+    set_raw_option(socket, level(group), name, &request)
+}
+
+/// Set an integer option that exists separately for IPv4 and IPv6.
+///
+/// On V4-mapped sockets, both are set, as the IPv4 option governs traffic to IPv4 groups.
+fn set_both(
+    socket: &impl AsRawFd,
+    bound: std::net::SocketAddr,
+    v4_mapped: bool,
+    v4_name: libc::c_int,
+    v6_name: libc::c_int,
+    value: libc::c_int,
+) -> Result<(), Error> {
+    if bound.is_ipv4() || v4_mapped {
+        set_raw_option(socket, libc::IPPROTO_IP, v4_name, &value)?;
+    }
+    if bound.is_ipv6() {
+        set_raw_option(socket, libc::IPPROTO_IPV6, v6_name, &value)?;
+    }
+    Ok(())
+}
+
+fn set_interface(
+    socket: &impl AsRawFd,
+    bound: std::net::SocketAddr,
+    v4_mapped: bool,
+    interface: u32,
+) -> Result<(), Error> {
+    if bound.is_ipv4() || v4_mapped {
+        let request = libc::ip_mreqn {
+            imr_multiaddr: libc::in_addr { s_addr: 0 },
+            imr_address: libc::in_addr { s_addr: 0 },
+            imr_ifindex: interface as _,
+        };
+        set_raw_option(socket, libc::IPPROTO_IP, libc::IP_MULTICAST_IF, &request)?;
+    }
+    if bound.is_ipv6() {
+        let interface = interface as libc::c_int;
+        set_raw_option(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MULTICAST_IF,
+            &interface,
+        )?;
+    }
+    Ok(())
+}
+
+macro_rules! impl_multicast {
+    ($socket:ident) => {
+        impl $socket {
+            /// Join a multicast group on the interface with the given index.
+            ///
+            /// With an interface index of 0, the operating system picks the interface. Datagrams
+            /// sent to the group are then received on the socket (provided it is bound to the
+            /// group's port), and report the group address as their local address.
+            pub fn join_multicast(
+                &self,
+                group: embedded_nal_async::IpAddr,
+                interface: u32,
+            ) -> Result<(), Error> {
+                membership(
+                    &self.socket,
+                    libc::MCAST_JOIN_GROUP,
+                    group,
+                    interface,
+                )
+            }
+
+            /// Leave a multicast group previously joined with
+            #[doc = concat!("[`.join_multicast()`](", stringify!($socket), "::join_multicast).")]
+            pub fn leave_multicast(
+                &self,
+                group: embedded_nal_async::IpAddr,
+                interface: u32,
+            ) -> Result<(), Error> {
+                membership(
+                    &self.socket,
+                    libc::MCAST_LEAVE_GROUP,
+                    group,
+                    interface,
+                )
+            }
+
+            /// Join a multicast group for datagrams from a single source only (source-specific
+            /// multicast).
+            ///
+            /// Like with any-source membership, an interface index of 0 lets the operating system
+            /// pick the interface. Further sources can be added by calling this repeatedly.
+            pub fn join_multicast_source(
+                &self,
+                group: embedded_nal_async::IpAddr,
+                source: embedded_nal_async::IpAddr,
+                interface: u32,
+            ) -> Result<(), Error> {
+                source_membership(
+                    &self.socket,
+                    libc::MCAST_JOIN_SOURCE_GROUP,
+                    group,
+                    source,
+                    interface,
+                )
+            }
+
+            /// Stop receiving datagrams from a source previously added with
+            #[doc = concat!("[`.join_multicast_source()`](", stringify!($socket), "::join_multicast_source).")]
+            pub fn leave_multicast_source(
+                &self,
+                group: embedded_nal_async::IpAddr,
+                source: embedded_nal_async::IpAddr,
+                interface: u32,
+            ) -> Result<(), Error> {
+                source_membership(
+                    &self.socket,
+                    libc::MCAST_LEAVE_SOURCE_GROUP,
+                    group,
+                    source,
+                    interface,
+                )
+            }
+
+            /// Set the interface on which datagrams to multicast groups are sent, unless a local
+            /// address carrying an interface is given in the send operation.
+            ///
+            /// An interface index of 0 reverts to the operating system's choice.
+            pub fn set_multicast_interface(&self, interface: u32) -> Result<(), Error> {
+                set_interface(&self.socket, self.bound_address, self.v4_mapped, interface)
+            }
+
+            /// Set the hop limit (IPv4: time to live) of datagrams sent to multicast groups.
+            ///
+            /// The operating system's default is 1, which keeps them on the local link.
+            pub fn set_multicast_hop_limit(&self, hop_limit: u8) -> Result<(), Error> {
+                set_both(
+                    &self.socket,
+                    self.bound_address,
+                    self.v4_mapped,
+                    libc::IP_MULTICAST_TTL,
+                    libc::IPV6_MULTICAST_HOPS,
+                    hop_limit.into(),
+                )
+            }
+
+            /// Set whether datagrams sent to multicast groups are also delivered to the sending
+            /// host's own sockets that joined the group.
+            ///
+            /// The operating system's default is to loop them back.
+            pub fn set_multicast_loop(&self, enabled: bool) -> Result<(), Error> {
+                set_both(
+                    &self.socket,
+                    self.bound_address,
+                    self.v4_mapped,
+                    libc::IP_MULTICAST_LOOP,
+                    libc::IPV6_MULTICAST_LOOP,
+                    enabled.into(),
+                )
+            }
+        }
+    };
+}
+
+impl_multicast!(UniquelyBoundSocket);
+impl_multicast!(MultiplyBoundSocket);
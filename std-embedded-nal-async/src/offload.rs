@@ -0,0 +1,232 @@
+//! Segmentation offload for bulk transfers on unconnected UDP sockets
+//!
+//! With generic segmentation offload (`UDP_SEGMENT`), many equally sized datagrams are passed to
+//! the kernel in a single buffer and split up as late as possible. With generic receive offload
+//! (`UDP_GRO`), the kernel hands out consecutive datagrams of a flow in a single buffer, which is
+//! split back up here.
+//!
+//! Both fall back to handling one datagram at a time where the kernel or the network interface
+//! does not support them.
+
+use crate::metadata::Metadata;
+use crate::socket::set_raw_option;
+use crate::udp;
+use crate::{MultiplyBoundSocket, UniquelyBoundSocket};
+use nix::libc;
+use std::io::Error;
+use std::num::NonZeroU16;
+use std::os::unix::io::AsRawFd;
+
+/// Largest number of segments the kernel accepts in a single send (`UDP_MAX_SEGMENTS`; newer
+/// kernels accept more)
+const MAX_SEGMENTS: usize = 64;
+
+/// Largest UDP payload in an IPv4 packet, which is also within the limit of IPv6
+const MAX_PAYLOAD: usize = 65507;
+
+/// Datagrams received in a single buffer through
+/// [`receive_coalesced()`](MultiplyBoundSocket::receive_coalesced)
+///
+/// This iterates over the individual datagrams.
+#[derive(Debug, Clone)]
+pub struct Segments<'a> {
+    data: &'a [u8],
+    segment_size: usize,
+    done: bool,
+}
+
+impl<'a> Segments<'a> {
+    fn new(data: &'a [u8], segment_size: usize) -> Self {
+        Self {
+            data,
+            // Only 0 for a single empty datagram, which is still produced once
+            segment_size: segment_size.max(1),
+            done: false,
+        }
+    }
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.done {
+            return None;
+        }
+        let (segment, rest) = self.data.split_at(self.segment_size.min(self.data.len()));
+        self.data = rest;
+        self.done = rest.is_empty();
+        Some(segment)
+    }
+}
+
+/// Enable generic receive offload on a socket, tolerating kernels that do not support it.
+pub(crate) fn enable_receive_offload(socket: &impl AsRawFd) -> Result<(), Error> {
+    let enabled: libc::c_int = 1;
+    match set_raw_option(socket, libc::SOL_UDP, libc::UDP_GRO, &enabled) {
+        Err(e) if e.raw_os_error() == Some(libc::ENOPROTOOPT) => Ok(()),
+        result => result,
+    }
+}
+
+macro_rules! impl_offload {
+    ($socket:ident) => {
+        impl $socket {
+            /// Send `data` as a sequence of datagrams of `segment_size` bytes each (the last one
+            /// may be shorter), all from the same local address to the same remote address.
+            ///
+            /// Where available, the datagrams are handed to the kernel in one piece and only split
+            /// up late (generic segmentation offload). Otherwise, or when the network interface
+            /// rejects it, they are sent one by one.
+            pub async fn send_segmented(
+                &mut self,
+                local: embedded_nal_async::SocketAddr,
+                remote: embedded_nal_async::SocketAddr,
+                data: &[u8],
+                segment_size: NonZeroU16,
+            ) -> Result<(), Error> {
+                let local = udp::to_socket(local, self.v4_mapped);
+                let remote = udp::to_socket(remote, self.v4_mapped);
+                let segment_size = usize::from(segment_size.get());
+
+                if self.gso_supported.is_none() {
+                    // Setting the socket's default segment size to 0 (disabled) only succeeds on
+                    // kernels that support segmentation offload
+                    let disabled: libc::c_int = 0;
+                    self.gso_supported = Some(
+                        set_raw_option(
+                            self.socket.get_ref(),
+                            libc::SOL_UDP,
+                            libc::UDP_SEGMENT,
+                            &disabled,
+                        )
+                        .is_ok(),
+                    );
+                }
+
+                let mut done = 0;
+                if self.gso_supported == Some(true) {
+                    let per_send = MAX_SEGMENTS.min(MAX_PAYLOAD / segment_size).max(1);
+                    for chunk in data.chunks(per_send * segment_size) {
+                        let mut control = udp::send_control(
+                            self.bound_address,
+                            &self.interfaces,
+                            local,
+                            remote,
+                            &Metadata::default(),
+                        )?
+                        .unwrap_or_default();
+                        if chunk.len() > segment_size {
+                            control.push(libc::SOL_UDP, libc::UDP_SEGMENT, segment_size as u16);
+                        }
+                        let sent = self
+                            .socket
+                            .write_with(|s| {
+                                let sent_len = crate::metadata::sendmsg(
+                                    s.as_raw_fd(),
+                                    chunk,
+                                    Some(remote),
+                                    &control,
+                                )?;
+                                udp::check_sent(sent_len, chunk)
+                            })
+                            .await;
+                        match sent {
+                            // The interface can not do the checksums of the segments
+                            Err(e) if e.raw_os_error() == Some(libc::EIO) => {
+                                self.gso_supported = Some(false);
+                                break;
+                            }
+                            sent => sent.map_err(|e| crate::pmtu::classify(e, &self.socket))?,
+                        }
+                        done += chunk.len();
+                    }
+                }
+
+                for segment in data[done..].chunks(segment_size) {
+                    udp::send_from(
+                        &self.socket,
+                        self.bound_address,
+                        &self.interfaces,
+                        local,
+                        remote,
+                        &Metadata::default(),
+                        segment,
+                    )
+                    .await?;
+                }
+                Ok(())
+            }
+
+            /// Ask the kernel to coalesce received datagrams (generic receive offload), so that
+            /// [`receive_coalesced()`](Self::receive_coalesced) can take many at once.
+            ///
+            /// As datagrams then arrive coalesced in all receive operations on the socket,
+            /// `receive_coalesced()` should be the only one used from then on. To enable this
+            /// before the socket is bound, use
+            /// [`SocketOptions::receive_offload`](crate::SocketOptions::receive_offload).
+            ///
+            /// Where the kernel does not support this, datagrams keep arriving one at a time, and
+            /// this succeeds all the same.
+            pub fn enable_receive_offload(&mut self) -> Result<(), Error> {
+                enable_receive_offload(self.socket.get_ref())
+            }
+
+            /// Receive one or more consecutive datagrams from the same remote address to the same
+            /// local address, and report their addresses along with the individual datagrams.
+            ///
+            /// Datagrams only arrive coalesced once generic receive offload is enabled on the socket
+            /// (see [`enable_receive_offload()`](Self::enable_receive_offload)); otherwise, or
+            /// where the kernel does not support it, every call produces a single datagram.
+            /// Coalesced datagrams can add up to 64 KiB; if they exceed the buffer, the excess is
+            /// lost.
+            pub async fn receive_coalesced<'b>(
+                &mut self,
+                buffer: &'b mut [u8],
+            ) -> Result<
+                (
+                    embedded_nal_async::SocketAddr,
+                    embedded_nal_async::SocketAddr,
+                    Segments<'b>,
+                ),
+                Error,
+            > {
+                let (local, remote, received) = udp::receive_with_control(
+                    &self.socket,
+                    &mut self.interfaces,
+                    buffer,
+                    self.bound_address,
+                )
+                .await?;
+                let data = &buffer[..received.length.min(buffer.len())];
+                Ok((
+                    udp::to_api(local, self.v4_mapped),
+                    udp::to_api(remote, self.v4_mapped),
+                    Segments::new(data, received.segment_size.unwrap_or(received.length)),
+                ))
+            }
+        }
+    };
+}
+
+impl_offload!(UniquelyBoundSocket);
+impl_offload!(MultiplyBoundSocket);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments() {
+        let split: Vec<_> = Segments::new(b"abcdefg", 3).collect();
+        assert_eq!(split, [&b"abc"[..], b"def", b"g"]);
+
+        // A single datagram that was truncated
+        let split: Vec<_> = Segments::new(b"abc", 10).collect();
+        assert_eq!(split, [&b"abc"[..]]);
+
+        // An empty datagram is still a datagram
+        let split: Vec<_> = Segments::new(b"", 0).collect();
+        assert_eq!(split, [&b""[..]]);
+    }
+}
@@ -0,0 +1,177 @@
+//! Path MTU discovery and handling of datagrams that exceed it
+//!
+//! Whether datagrams are fragmented or rejected when they exceed the path MTU is set per socket.
+//! Rejected datagrams fail to send with a [`MessageTooLarge`] error, which carries the path MTU
+//! where the socket knows it, so that protocols can adapt their message sizes.
+
+use crate::socket::{get_raw_option, set_raw_option};
+use crate::{ConnectedSocket, MultiplyBoundSocket, UniquelyBoundSocket};
+use nix::libc;
+use nix::sys::socket::{getpeername, getsockname, SockaddrStorage};
+use std::io::Error;
+use std::os::unix::io::AsRawFd;
+
+/// How the operating system treats datagrams that exceed the path MTU
+/// (`IP_MTU_DISCOVER` / `IPV6_MTU_DISCOVER`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PathMtuDiscovery {
+    /// Do not discover the path MTU; IPv4 datagrams are sent without the don't-fragment flag and
+    /// may be fragmented by routers.
+    Dont,
+    /// Discover the path MTU, and fragment datagrams that exceed it locally.
+    Want,
+    /// Discover the path MTU, and reject datagrams that exceed it with [`MessageTooLarge`].
+    Do,
+    /// Like [`Do`](Self::Do), but only reject datagrams that exceed the interface MTU, so that
+    /// larger sizes can be probed.
+    Probe,
+}
+
+impl PathMtuDiscovery {
+    fn value(self) -> libc::c_int {
+        // The IPv6 values are the same
+        match self {
+            PathMtuDiscovery::Dont => libc::IP_PMTUDISC_DONT,
+            PathMtuDiscovery::Want => libc::IP_PMTUDISC_WANT,
+            PathMtuDiscovery::Do => libc::IP_PMTUDISC_DO,
+            PathMtuDiscovery::Probe => libc::IP_PMTUDISC_PROBE,
+        }
+    }
+}
+
+/// An std::io::Error compatible error type expressing that a datagram was not sent because it
+/// exceeds the path MTU (`EMSGSIZE`)
+///
+/// It is returned inside an error of the [`Other`](std::io::ErrorKind::Other) kind, which is not
+/// distinctive: Other errors can have that kind as well. [`MessageTooLarge::from_error()`] is the
+/// only supported way of recognizing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MessageTooLarge {
+    /// The path MTU (including IP and UDP headers), if the socket is connected and the operating
+    /// system knows it
+    pub path_mtu: Option<usize>,
+}
+
+impl core::fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.path_mtu {
+            Some(mtu) => write!(f, "Message too large for path MTU of {mtu} bytes"),
+            None => write!(f, "Message too large for path MTU"),
+        }
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+impl MessageTooLarge {
+    /// Recognize a [`MessageTooLarge`] error among the errors returned from sending.
+    ///
+    /// This looks at the error's inner error rather than its kind, which is not specific to it.
+    pub fn from_error(error: &Error) -> Option<&MessageTooLarge> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+/// Turn an `EMSGSIZE` error from sending on the socket into a [`MessageTooLarge`] error, passing
+/// on any other error.
+pub(crate) fn classify(error: Error, socket: &impl AsRawFd) -> Error {
+    if error.raw_os_error() != Some(libc::EMSGSIZE) {
+        return error;
+    }
+    Error::other(MessageTooLarge {
+        path_mtu: path_mtu(socket).ok(),
+    })
+}
+
+/// Query the path MTU towards the peer of a connected socket.
+///
+/// On IPv6 sockets connected to V4-mapped peers, the IPv4 option is queried, as that is what
+/// governs their traffic.
+fn path_mtu(socket: &impl AsRawFd) -> Result<usize, Error> {
+    let peer: SockaddrStorage = getpeername(socket.as_raw_fd())?;
+    let mapped = peer
+        .as_sockaddr_in6()
+        .map(|peer| peer.ip().to_ipv4_mapped().is_some());
+    let mtu: libc::c_int = match mapped {
+        Some(false) => get_raw_option(socket, libc::IPPROTO_IPV6, libc::IPV6_MTU)?,
+        _ => get_raw_option(socket, libc::IPPROTO_IP, libc::IP_MTU)?,
+    };
+    Ok(mtu as usize)
+}
+
+/// Find which of IPv4 and IPv6 traffic the socket handles.
+pub(crate) fn families(socket: &impl AsRawFd) -> Result<(bool, bool), Error> {
+    let local: SockaddrStorage = getsockname(socket.as_raw_fd())?;
+    if local.as_sockaddr_in6().is_none() {
+        return Ok((true, false));
+    }
+    let v6_only: libc::c_int = get_raw_option(socket, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY)?;
+    Ok((v6_only == 0, true))
+}
+
+fn set_discovery(socket: &impl AsRawFd, mode: PathMtuDiscovery) -> Result<(), Error> {
+    let (v4, v6) = families(socket)?;
+    if v4 {
+        set_raw_option(
+            socket,
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            &mode.value(),
+        )?;
+    }
+    if v6 {
+        set_raw_option(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            &mode.value(),
+        )?;
+    }
+    Ok(())
+}
+
+macro_rules! impl_pmtu {
+    ($socket:ident, $field:tt) => {
+        impl $socket {
+            /// Set how datagrams that exceed the path MTU are treated.
+            ///
+            /// On sockets that handle IPv4 and IPv6 traffic alike (V4-mapped sockets), the mode
+            /// is set for both. The operating system's default is usually
+            /// [`Want`](PathMtuDiscovery::Want).
+            pub fn set_path_mtu_discovery(&self, mode: PathMtuDiscovery) -> Result<(), Error> {
+                set_discovery(&self.$field, mode)
+            }
+
+            /// Set whether IPv6 datagrams that exceed the path MTU are rejected with
+            /// [`MessageTooLarge`] rather than fragmented locally (`IPV6_DONTFRAG`).
+            ///
+            /// This only applies to IPv6 sockets, and only to their IPv6 traffic; IPv4 traffic is
+            /// controlled through [`PathMtuDiscovery::Do`].
+            pub fn set_dont_fragment(&self, enabled: bool) -> Result<(), Error> {
+                let enabled = libc::c_int::from(enabled);
+                set_raw_option(
+                    &self.$field,
+                    libc::IPPROTO_IPV6,
+                    libc::IPV6_DONTFRAG,
+                    &enabled,
+                )
+            }
+        }
+    };
+}
+
+impl_pmtu!(ConnectedSocket, socket);
+impl_pmtu!(UniquelyBoundSocket, socket);
+impl_pmtu!(MultiplyBoundSocket, socket);
+
+impl ConnectedSocket {
+    /// Report the operating system's current estimate of the path MTU towards the peer, including
+    /// IP and UDP headers (`IP_MTU` / `IPV6_MTU`).
+    ///
+    /// The estimate only shrinks below the interface MTU once path MTU discovery is enabled and
+    /// the network reported a smaller MTU.
+    pub fn path_mtu(&self) -> Result<usize, Error> {
+        path_mtu(&self.socket)
+    }
+}
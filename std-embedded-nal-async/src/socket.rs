@@ -0,0 +1,181 @@
+//! Creation of operating system sockets according to a stack's configuration
+//!
+//! Sockets are created step by step (rather than through the standard library's constructors), so
+//! that options can be applied before they are bound.
+
+use crate::builder::{Config, SocketRole};
+use nix::sys::socket::SockaddrStorage;
+use nix::sys::socket::{self, setsockopt, sockopt, AddressFamily, SockFlag, SockType};
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::{AsFd, AsRawFd, OwnedFd};
+
+fn new_socket(addr: SocketAddr, ty: SockType, config: &Config) -> io::Result<OwnedFd> {
+    let family = match addr {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    };
+    let fd = socket::socket(family, ty, SockFlag::SOCK_CLOEXEC, None)?;
+    if let Some(device) = &config.bind_device {
+        setsockopt(&fd, sockopt::BindToDevice, device)?;
+    }
+    Ok(fd)
+}
+
+/// Run the configured socket hook, if any.
+fn run_hook(fd: &OwnedFd, role: SocketRole, config: &Config) -> io::Result<()> {
+    match &config.socket_hook {
+        Some(hook) => (hook.0)(fd.as_fd(), role),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn udp_bind(
+    local: SocketAddr,
+    role: SocketRole,
+    config: &Config,
+) -> io::Result<std::net::UdpSocket> {
+    let fd = new_socket(local, SockType::Datagram, config)?;
+
+    if config.v4_mapped && local.is_ipv6() {
+        // Not relying on the system default, which can be altered through sysctl
+        setsockopt(&fd, sockopt::Ipv6V6Only, &false)?;
+    }
+    apply_options(&fd, local, config)?;
+    run_hook(&fd, role, config)?;
+
+    socket::bind(fd.as_raw_fd(), &SockaddrStorage::from(local))?;
+
+    Ok(fd.into())
+}
+
+/// Create a TCP socket for connecting to `remote`, ready to be connected.
+pub(crate) fn tcp_socket(remote: SocketAddr, config: &Config) -> io::Result<OwnedFd> {
+    let fd = new_socket(remote, SockType::Stream, config)?;
+    run_hook(&fd, SocketRole::TcpConnection, config)?;
+    Ok(fd)
+}
+
+/// Connect a TCP socket to `remote`, waiting for the connection to be established.
+pub(crate) async fn tcp_connect(
+    remote: SocketAddr,
+    config: &Config,
+) -> io::Result<std::net::TcpStream> {
+    use nix::errno::Errno;
+
+    let sock = async_io::Async::new(std::net::TcpStream::from(tcp_socket(remote, config)?))?;
+    match socket::connect(sock.as_raw_fd(), &SockaddrStorage::from(remote)) {
+        Ok(()) => (),
+        Err(Errno::EINPROGRESS) | Err(Errno::EINTR) => {
+            // The socket becomes writable once the attempt completed, and reports its outcome
+            sock.writable().await?;
+            if let Some(e) = sock.get_ref().take_error()? {
+                return Err(e);
+            }
+        }
+        Err(e) => return Err(e.into()),
+    }
+    sock.into_inner()
+}
+
+/// Apply the configured socket options to a socket that is about to be bound to `local`.
+///
+/// Options that exist separately for IPv4 and IPv6 are set for both on V4-mapped sockets.
+fn apply_options(fd: &OwnedFd, local: SocketAddr, config: &Config) -> io::Result<()> {
+    use nix::libc;
+
+    let options = &config.socket_options;
+    if let Some(reuse_address) = options.reuse_address {
+        setsockopt(fd, sockopt::ReuseAddr, &reuse_address)?;
+    }
+    if let Some(reuse_port) = options.reuse_port {
+        setsockopt(fd, sockopt::ReusePort, &reuse_port)?;
+    }
+    if let Some(size) = options.receive_buffer_size {
+        setsockopt(fd, sockopt::RcvBuf, &size)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        setsockopt(fd, sockopt::SndBuf, &size)?;
+    }
+
+    let v4 = local.is_ipv4() || config.v4_mapped;
+    let v6 = local.is_ipv6();
+    if let Some(hop_limit) = options.hop_limit {
+        let hop_limit = libc::c_int::from(hop_limit);
+        if v4 {
+            set_raw_option(fd, libc::IPPROTO_IP, libc::IP_TTL, &hop_limit)?;
+        }
+        if v6 {
+            set_raw_option(fd, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, &hop_limit)?;
+        }
+    }
+    if let Some(dscp) = options.dscp {
+        let traffic_class = libc::c_int::from(dscp << 2);
+        if v4 {
+            set_raw_option(fd, libc::IPPROTO_IP, libc::IP_TOS, &traffic_class)?;
+        }
+        if v6 {
+            set_raw_option(fd, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, &traffic_class)?;
+        }
+    }
+    if options.receive_metadata == Some(true) {
+        crate::metadata::enable(fd)?;
+    }
+    if let Some(receive_timestamps) = options.receive_timestamps {
+        setsockopt(fd, sockopt::ReceiveTimestampns, &receive_timestamps)?;
+    }
+    if options.receive_offload == Some(true) {
+        crate::offload::enable_receive_offload(fd)?;
+    }
+    Ok(())
+}
+
+/// Set a socket option nix has no wrapper for from a plain value.
+pub(crate) fn set_raw_option<T>(
+    socket: &impl AsRawFd,
+    level: nix::libc::c_int,
+    name: nix::libc::c_int,
+    value: &T,
+) -> io::Result<()> {
+    // SAFETY: The value is a plain C structure or integer that outlives the call, and its
+    // size is passed along.
+    let result = unsafe {
+        nix::libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            value as *const T as *const nix::libc::c_void,
+            core::mem::size_of::<T>() as nix::libc::socklen_t,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Read a socket option nix has no wrapper for into a plain value.
+pub(crate) fn get_raw_option<T: Copy>(
+    socket: &impl AsRawFd,
+    level: nix::libc::c_int,
+    name: nix::libc::c_int,
+) -> io::Result<T> {
+    let mut value = core::mem::MaybeUninit::<T>::zeroed();
+    let mut len = core::mem::size_of::<T>() as nix::libc::socklen_t;
+    // SAFETY: The value is a plain C structure or integer with room for the given length, and
+    // starts out zeroed, so it is initialized even if the kernel writes less.
+    let result = unsafe {
+        nix::libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            value.as_mut_ptr() as *mut nix::libc::c_void,
+            &mut len,
+        )
+    };
+    match result {
+        // SAFETY: Zero-initialized plain data, possibly overwritten by the kernel
+        0 => Ok(unsafe { value.assume_init() }),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
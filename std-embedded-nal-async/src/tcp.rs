@@ -12,11 +12,9 @@ impl embedded_nal_async::TcpConnect for crate::Stack {
         &'a self,
         addr: embedded_nal_async::SocketAddr,
     ) -> Result<Self::Connection<'a>, Error> {
-        async_std::net::TcpStream::connect(async_std::net::SocketAddr::from(
-            conversion::SocketAddr::from(addr),
-        ))
-        .await
-        .map(TcpConnection)
+        let remote = conversion::SocketAddr::from(addr).into();
+        let stream = crate::socket::tcp_connect(remote, &self.config).await?;
+        Ok(TcpConnection(stream.into()))
     }
 }
 
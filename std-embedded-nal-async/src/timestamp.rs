@@ -0,0 +1,64 @@
+//! Kernel receive timestamps on unconnected UDP sockets
+//!
+//! Timestamps are taken by the operating system when a datagram arrives, and are thus free of the
+//! scheduling delays between the arrival and the completion of a receive operation.
+
+use crate::metadata::check_enabled;
+use crate::udp;
+use crate::{MultiplyBoundSocket, UniquelyBoundSocket};
+use nix::sys::socket::{setsockopt, sockopt};
+use std::io::Error;
+use std::time::SystemTime;
+
+macro_rules! impl_timestamp {
+    ($socket:ident) => {
+        impl $socket {
+            /// Enable kernel receive timestamps (`SO_TIMESTAMPNS`) on the socket.
+            ///
+            /// Datagrams that were queued on the socket before lack them; see
+            /// [`Metadata`](crate::Metadata) for how to enable them before the socket is bound.
+            pub fn enable_timestamps(&mut self) -> Result<(), Error> {
+                setsockopt(self.socket.get_ref(), sockopt::ReceiveTimestampns, &true)?;
+                self.timestamps_enabled = true;
+                Ok(())
+            }
+
+            /// Receive a datagram like
+            /// [`receive_into()`](embedded_nal_async::UnconnectedUdp::receive_into), and also report
+            /// the system time at which the kernel received it.
+            ///
+            /// This fails with an [`InvalidInput`](std::io::ErrorKind::InvalidInput) error unless
+            /// timestamps were enabled on the socket, see [`Metadata`](crate::Metadata).
+            pub async fn receive_into_with_timestamp(
+                &mut self,
+                buffer: &mut [u8],
+            ) -> Result<
+                (
+                    usize,
+                    embedded_nal_async::SocketAddr,
+                    embedded_nal_async::SocketAddr,
+                    Option<SystemTime>,
+                ),
+                Error,
+            > {
+                check_enabled(self.timestamps_enabled, "timestamps")?;
+                let (local, remote, received) = udp::receive_with_control(
+                    &self.socket,
+                    &mut self.interfaces,
+                    buffer,
+                    self.bound_address,
+                )
+                .await?;
+                Ok((
+                    received.length,
+                    udp::to_api(local, self.v4_mapped),
+                    udp::to_api(remote, self.v4_mapped),
+                    received.timestamp,
+                ))
+            }
+        }
+    };
+}
+
+impl_timestamp!(UniquelyBoundSocket);
+impl_timestamp!(MultiplyBoundSocket);
@@ -1,24 +1,85 @@
 //! UDP implementation on the standard stack for embedded-nal-async
 
 use crate::conversion;
+use crate::metadata::{self, Metadata};
 use std::io::Error;
 
 use std::os::unix::io::AsRawFd;
 
-pub struct ConnectedSocket(async_std::net::UdpSocket);
+pub struct ConnectedSocket {
+    pub(crate) socket: async_io::Async<std::net::UdpSocket>,
+    pub(crate) v4_mapped: bool,
+    /// Whether the options for receiving metadata are set
+    pub(crate) metadata_enabled: bool,
+}
 pub struct UniquelyBoundSocket {
-    socket: async_std::net::UdpSocket,
-    // By storing this, we avoid the whole recvmsg hell, which we can because there's really only
-    // one relevant address. (Alternatively, we could call `.local_addr()` over and over).
-    bound_address: embedded_nal_async::SocketAddr,
+    pub(crate) socket: async_io::Async<std::net::UdpSocket>,
+    // By storing this, we avoid the whole recvmsg hell when bound to a concrete address, because
+    // there's really only one relevant address. (Alternatively, we could call `.local_addr()` over
+    // and over). When bound to an unspecified address, packet info is enabled, and this is only
+    // used for its port. (Like all addresses stored on sockets, this is V4-mapped in V4-mapped
+    // mode).
+    pub(crate) bound_address: std::net::SocketAddr,
+    pub(crate) interfaces: Ipv4Interfaces,
+    pub(crate) v4_mapped: bool,
+    /// Whether the options for receiving metadata are set
+    pub(crate) metadata_enabled: bool,
+    /// Whether receive timestamps are enabled
+    pub(crate) timestamps_enabled: bool,
+    /// Whether segmentation offload works, once that was probed
+    pub(crate) gso_supported: Option<bool>,
 }
 pub struct MultiplyBoundSocket {
-    socket: async_io::Async<std::net::UdpSocket>,
-    // Storing this so we can return a full SocketAddr, even though pktinfo doesn't provide that
-    // information
-    port: u16,
+    pub(crate) socket: async_io::Async<std::net::UdpSocket>,
+    // Storing this so we can return a full SocketAddr, even though pktinfo doesn't provide the
+    // port, and to check the local addresses given in send
+    pub(crate) bound_address: std::net::SocketAddr,
+    pub(crate) interfaces: Ipv4Interfaces,
+    pub(crate) v4_mapped: bool,
+    /// Whether the options for receiving metadata are set
+    pub(crate) metadata_enabled: bool,
+    /// Whether receive timestamps are enabled
+    pub(crate) timestamps_enabled: bool,
+    /// Whether segmentation offload works, once that was probed
+    pub(crate) gso_supported: Option<bool>,
+}
+
+/// Convert an address given at the API to the form used on the socket.
+pub(crate) fn to_socket(
+    addr: embedded_nal_async::SocketAddr,
+    v4_mapped: bool,
+) -> std::net::SocketAddr {
+    let addr = conversion::SocketAddr::from(addr);
+    if v4_mapped { addr.mapped() } else { addr }.into()
+}
+
+/// Convert an address used on the socket to the form given out at the API.
+pub(crate) fn to_api(
+    addr: std::net::SocketAddr,
+    v4_mapped: bool,
+) -> embedded_nal_async::SocketAddr {
+    let addr = conversion::SocketAddr::from(addr);
+    if v4_mapped { addr.unmapped() } else { addr }.into()
+}
+
+/// Whether an address on a socket is unspecified, including the V4-mapped unspecified address
+pub(crate) fn is_wildcard(ip: std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V6(ip) => {
+            ip.is_unspecified() || ip.to_ipv4_mapped() == Some(std::net::Ipv4Addr::UNSPECIFIED)
+        }
+        ip => ip.is_unspecified(),
+    }
 }
 
+/// Interfaces on which IPv4 local addresses were last seen, as learned from received packet info
+///
+/// IPv6 addresses carry their interface as the scope ID where it matters. IPv4 addresses have no
+/// such field, so the interface is remembered per local address, and used when sending from that
+/// address.
+#[derive(Default)]
+pub(crate) struct Ipv4Interfaces(std::collections::HashMap<std::net::Ipv4Addr, u32>);
+
 impl embedded_nal_async::UdpStack for crate::Stack {
     type Error = Error;
     type Connected = ConnectedSocket;
@@ -30,21 +91,24 @@ impl embedded_nal_async::UdpStack for crate::Stack {
         local: embedded_nal_async::SocketAddr,
         remote: embedded_nal_async::SocketAddr,
     ) -> Result<(embedded_nal_async::SocketAddr, Self::Connected), Self::Error> {
-        let sock = async_std::net::UdpSocket::bind(async_std::net::SocketAddr::from(
-            conversion::SocketAddr::from(local),
-        ))
-        .await?;
+        let v4_mapped = self.config.v4_mapped;
+        let sock = crate::socket::udp_bind(
+            to_socket(local, v4_mapped),
+            crate::SocketRole::UdpConnected,
+            &self.config,
+        )?;
 
-        sock.connect(async_std::net::SocketAddr::from(
-            conversion::SocketAddr::from(remote),
-        ))
-        .await?;
+        sock.connect(to_socket(remote, v4_mapped))?;
 
         let final_local = sock.local_addr()?;
 
         Ok((
-            conversion::SocketAddr::from(final_local).into(),
-            ConnectedSocket(sock),
+            to_api(final_local, v4_mapped),
+            ConnectedSocket {
+                metadata_enabled: metadata::enabled(&sock)?,
+                socket: async_io::Async::new(sock)?,
+                v4_mapped,
+            },
         ))
     }
 
@@ -52,56 +116,100 @@ impl embedded_nal_async::UdpStack for crate::Stack {
         &self,
         local: embedded_nal_async::SocketAddr,
     ) -> Result<(embedded_nal_async::SocketAddr, Self::UniquelyBound), Self::Error> {
-        let sock = async_std::net::UdpSocket::bind(async_std::net::SocketAddr::from(
-            conversion::SocketAddr::from(local),
-        ))
-        .await?;
+        let v4_mapped = self.config.v4_mapped;
+        let sock = crate::socket::udp_bind(
+            to_socket(local, v4_mapped),
+            crate::SocketRole::UdpUniquelyBound,
+            &self.config,
+        )?;
 
-        let final_local = sock.local_addr()?;
-        let final_local = conversion::SocketAddr::from(final_local).into();
+        UniquelyBoundSocket::new(sock, v4_mapped)
+    }
+
+    async fn bind_multiple(
+        &self,
+        local: embedded_nal_async::SocketAddr,
+    ) -> Result<Self::MultiplyBound, Self::Error> {
+        let v4_mapped = self.config.v4_mapped;
+        let sock = crate::socket::udp_bind(
+            to_socket(local, v4_mapped),
+            crate::SocketRole::UdpMultiplyBound,
+            &self.config,
+        )?;
+
+        MultiplyBoundSocket::new(sock, v4_mapped)
+    }
+}
+
+impl UniquelyBoundSocket {
+    /// Wrap a bound socket, and report its final local address.
+    ///
+    /// If the socket is bound to an unspecified address, packet info is enabled, so that the
+    /// actual local address of each received datagram can be reported.
+    pub(crate) fn new(
+        socket: std::net::UdpSocket,
+        v4_mapped: bool,
+    ) -> Result<(embedded_nal_async::SocketAddr, Self), Error> {
+        let final_local = socket.local_addr()?;
+        if is_wildcard(final_local.ip()) {
+            enable_pktinfo(&socket, final_local)?;
+        }
 
         Ok((
-            final_local,
+            to_api(final_local, v4_mapped),
             UniquelyBoundSocket {
-                socket: sock,
+                metadata_enabled: metadata::enabled(&socket)?,
+                timestamps_enabled: timestamps_enabled(&socket)?,
+                socket: async_io::Async::new(socket)?,
                 bound_address: final_local,
+                interfaces: Default::default(),
+                v4_mapped,
+                gso_supported: None,
             },
         ))
     }
 
-    async fn bind_multiple(
-        &self,
-        local: embedded_nal_async::SocketAddr,
-    ) -> Result<Self::MultiplyBound, Self::Error> {
-        let is_v4 = matches!(&local, embedded_nal_async::SocketAddr::V4(_));
+    fn is_wildcard(&self) -> bool {
+        is_wildcard(self.bound_address.ip())
+    }
+}
 
-        let mut sock =
-            async_io::Async::<std::net::UdpSocket>::bind(conversion::SocketAddr::from(local))?;
+/// Find whether receive timestamps are enabled on a socket, however they were enabled.
+fn timestamps_enabled(socket: &std::net::UdpSocket) -> Result<bool, Error> {
+    Ok(nix::sys::socket::getsockopt(
+        socket,
+        nix::sys::socket::sockopt::ReceiveTimestampns,
+    )?)
+}
 
-        let plain_sock = sock.get_mut();
+/// Enable the packet info socket option matching the bound address' family.
+pub(crate) fn enable_pktinfo(
+    socket: &std::net::UdpSocket,
+    local: std::net::SocketAddr,
+) -> Result<(), Error> {
+    if local.is_ipv4() {
+        nix::sys::socket::setsockopt(socket, nix::sys::socket::sockopt::Ipv4PacketInfo, &true)?;
+    } else {
+        nix::sys::socket::setsockopt(socket, nix::sys::socket::sockopt::Ipv6RecvPacketInfo, &true)?;
+    }
+    Ok(())
+}
 
-        if is_v4 {
-            nix::sys::socket::setsockopt(
-                &plain_sock,
-                nix::sys::socket::sockopt::Ipv4PacketInfo,
-                &true,
-            )?;
-        } else {
-            nix::sys::socket::setsockopt(
-                &plain_sock,
-                nix::sys::socket::sockopt::Ipv6RecvPacketInfo,
-                &true,
-            )?;
-        }
+impl MultiplyBoundSocket {
+    /// Wrap a bound socket, enabling the packet info socket options it relies on.
+    pub(crate) fn new(socket: std::net::UdpSocket, v4_mapped: bool) -> Result<Self, Error> {
+        let local = socket.local_addr()?;
 
-        let mut local_port = local.port();
-        if local_port == 0 {
-            local_port = plain_sock.local_addr()?.port();
-        }
+        enable_pktinfo(&socket, local)?;
 
         Ok(MultiplyBoundSocket {
-            socket: sock,
-            port: local_port,
+            metadata_enabled: metadata::enabled(&socket)?,
+            timestamps_enabled: timestamps_enabled(&socket)?,
+            socket: async_io::Async::new(socket)?,
+            bound_address: local,
+            interfaces: Default::default(),
+            v4_mapped,
+            gso_supported: None,
         })
     }
 }
@@ -110,16 +218,25 @@ impl embedded_nal_async::ConnectedUdp for ConnectedSocket {
     type Error = Error;
 
     async fn send(&mut self, data: &[u8]) -> Result<(), Self::Error> {
-        let sent_len = self.0.send(data).await?;
-        assert!(
-            sent_len == data.len(),
-            "Datagram was not sent in a single operation"
-        );
-        Ok(())
+        let sent_len = self
+            .socket
+            .send(data)
+            .await
+            .map_err(|e| crate::pmtu::classify(e, &self.socket))?;
+        check_sent(sent_len, data)
     }
 
     async fn receive_into(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
-        self.0.recv(buffer).await
+        // Not using plain recv, as that would report the truncated length
+        self.socket
+            .read_with(|s| {
+                Ok(nix::sys::socket::recv(
+                    s.as_raw_fd(),
+                    buffer,
+                    nix::sys::socket::MsgFlags::MSG_TRUNC,
+                )?)
+            })
+            .await
     }
 }
 
@@ -132,17 +249,16 @@ impl embedded_nal_async::UnconnectedUdp for UniquelyBoundSocket {
         remote: embedded_nal_async::SocketAddr,
         data: &[u8],
     ) -> Result<(), Self::Error> {
-        debug_assert!(
-            local == self.bound_address,
-            "A socket created from bind_single must always provide its original local address (or the one returned from a receive) in send"
-        );
-        let remote: async_std::net::SocketAddr = conversion::SocketAddr::from(remote).into();
-        let sent_len = self.socket.send_to(data, remote).await?;
-        assert!(
-            sent_len == data.len(),
-            "Datagram was not sent in a single operation"
-        );
-        Ok(())
+        send_from(
+            &self.socket,
+            self.bound_address,
+            &self.interfaces,
+            to_socket(local, self.v4_mapped),
+            to_socket(remote, self.v4_mapped),
+            &Metadata::default(),
+            data,
+        )
+        .await
     }
 
     async fn receive_into(
@@ -156,9 +272,35 @@ impl embedded_nal_async::UnconnectedUdp for UniquelyBoundSocket {
         ),
         Self::Error,
     > {
-        let (length, remote) = self.socket.recv_from(buffer).await?;
-        let remote = conversion::SocketAddr::from(remote).into();
-        Ok((length, self.bound_address, remote))
+        let (length, local, remote) = if self.is_wildcard() {
+            receive_with_pktinfo(
+                &self.socket,
+                &mut self.interfaces,
+                buffer,
+                self.bound_address,
+            )
+            .await?
+        } else {
+            let (length, remote) = self
+                .socket
+                .read_with(|s| {
+                    let mut iov = [std::io::IoSliceMut::new(buffer)];
+                    let received = nix::sys::socket::recvmsg(
+                        s.as_raw_fd(),
+                        &mut iov,
+                        None,
+                        nix::sys::socket::MsgFlags::MSG_TRUNC,
+                    )?;
+                    Ok((received.bytes, received.address))
+                })
+                .await?;
+            (length, self.bound_address, remote_address(remote)?)
+        };
+        Ok((
+            length,
+            to_api(local, self.v4_mapped),
+            to_api(remote, self.v4_mapped),
+        ))
     }
 }
 
@@ -171,67 +313,16 @@ impl embedded_nal_async::UnconnectedUdp for MultiplyBoundSocket {
         remote: embedded_nal_async::SocketAddr,
         data: &[u8],
     ) -> Result<(), Self::Error> {
-        if local.port() != 0 {
-            debug_assert_eq!(
-                local.port(),
-                self.port,
-                "Packets can only be sent from the locally bound to port"
-            );
-        }
-        let remote: async_std::net::SocketAddr = conversion::SocketAddr::from(remote).into();
-        match remote {
-            // The whole cases are distinct as send_msg is polymorphic
-            async_std::net::SocketAddr::V6(remote) => {
-                // Taking this step on foot due to https://github.com/nix-rust/nix/issues/1754
-                let remote = nix::sys::socket::SockaddrIn6::from(remote);
-                let local_pktinfo = conversion::IpAddr::from(local.ip()).into();
-                let control = [nix::sys::socket::ControlMessage::Ipv6PacketInfo(
-                    &local_pktinfo,
-                )];
-                self.socket
-                    .write_with(|s| {
-                        let sent_len = nix::sys::socket::sendmsg(
-                            s.as_raw_fd(),
-                            &[std::io::IoSlice::new(data)],
-                            // FIXME this ignores the IP part of the local address
-                            &control,
-                            nix::sys::socket::MsgFlags::empty(),
-                            Some(&remote),
-                        )?;
-                        assert!(
-                            sent_len == data.len(),
-                            "Datagram was not sent in a single operation"
-                        );
-                        Ok(())
-                    })
-                    .await
-            }
-            async_std::net::SocketAddr::V4(remote) => {
-                // Taking this step on foot due to https://github.com/nix-rust/nix/issues/1754
-                let remote = nix::sys::socket::SockaddrIn::from(remote);
-                let local_pktinfo = conversion::IpAddr::from(local.ip()).into();
-                let control = [nix::sys::socket::ControlMessage::Ipv4PacketInfo(
-                    &local_pktinfo,
-                )];
-                self.socket
-                    .write_with(|s| {
-                        let sent_len = nix::sys::socket::sendmsg(
-                            s.as_raw_fd(),
-                            &[std::io::IoSlice::new(data)],
-                            // FIXME this ignores the IP part of the local address
-                            &control,
-                            nix::sys::socket::MsgFlags::empty(),
-                            Some(&remote),
-                        )?;
-                        assert!(
-                            sent_len == data.len(),
-                            "Datagram was not sent in a single operation"
-                        );
-                        Ok(())
-                    })
-                    .await
-            }
-        }
+        send_from(
+            &self.socket,
+            self.bound_address,
+            &self.interfaces,
+            to_socket(local, self.v4_mapped),
+            to_socket(remote, self.v4_mapped),
+            &Metadata::default(),
+            data,
+        )
+        .await
     }
 
     async fn receive_into(
@@ -245,53 +336,310 @@ impl embedded_nal_async::UnconnectedUdp for MultiplyBoundSocket {
         ),
         Self::Error,
     > {
-        let (length, remote, local) = self.socket.read_with(|s| {
-            let mut iov = [std::io::IoSliceMut::new(buffer)];
-            let mut cmsg = nix::cmsg_space!(nix::libc::in6_pktinfo);
-            let received = nix::sys::socket::recvmsg(
-                s.as_raw_fd(),
-                &mut iov,
-                Some(&mut cmsg),
-                nix::sys::socket::MsgFlags::MSG_TRUNC,
-                )
-                .map_err(Error::from)?;
-            let local = match received.cmsgs().next() {
-                Some(nix::sys::socket::ControlMessageOwned::Ipv6PacketInfo(pi)) => {
-                    embedded_nal_async::SocketAddr::new(
-                        conversion::IpAddr::from(pi).into(),
-                        self.port,
-                        )
-                },
-                Some(nix::sys::socket::ControlMessageOwned::Ipv4PacketInfo(pi)) => {
-                    embedded_nal_async::SocketAddr::new(
-                        conversion::IpAddr::from(pi).into(),
-                        self.port,
-                        )
-                },
-                _ => panic!("Operating system failed to send IPv4/IPv6 packet info after acknowledging the socket option")
-            };
-            Ok((received.bytes, received.address, local))
-        }).await?;
-
-        let remote: nix::sys::socket::SockaddrStorage =
-            remote.expect("recvmsg on UDP always returns a remote address");
-        // Taking this step on foot due to https://github.com/nix-rust/nix/issues/1754
-        let remote = match (remote.as_sockaddr_in6(), remote.as_sockaddr_in()) {
-            (Some(remote), None) => std::net::SocketAddr::V6(std::net::SocketAddrV6::new(
-                remote.ip(),
-                remote.port(),
-                remote.flowinfo(),
-                remote.scope_id(),
-            )),
-            (None, Some(remote)) => std::net::SocketAddr::V4(std::net::SocketAddrV4::new(
-                remote.ip().into(),
-                remote.port(),
-            )),
-            _ => panic!("Unexpected address type"),
-        };
+        let (length, local, remote) = receive_with_pktinfo(
+            &self.socket,
+            &mut self.interfaces,
+            buffer,
+            self.bound_address,
+        )
+        .await?;
+        Ok((
+            length,
+            to_api(local, self.v4_mapped),
+            to_api(remote, self.v4_mapped),
+        ))
+    }
+}
+
+/// An std::io::Error compatible error type expressing that a local address given in a send
+/// operation can not be used with the socket
+#[derive(Debug)]
+struct LocalAddressMismatch(&'static str);
+
+impl core::fmt::Display for LocalAddressMismatch {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Local address does not match the socket: {}", self.0)
+    }
+}
+
+impl std::error::Error for LocalAddressMismatch {}
+
+/// An std::io::Error compatible error type expressing that a datagram was only sent partially
+#[derive(Debug)]
+struct IncompleteSend;
+
+impl core::fmt::Display for IncompleteSend {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Datagram was not sent in a single operation")
+    }
+}
+
+impl std::error::Error for IncompleteSend {}
+
+/// An std::io::Error compatible error type expressing that the operating system did not report
+/// the local address of a received datagram, even though it was asked to
+#[derive(Debug)]
+struct MissingPacketInfo;
+
+impl core::fmt::Display for MissingPacketInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "Operating system failed to send IPv4/IPv6 packet info after acknowledging the socket option"
+        )
+    }
+}
+
+impl std::error::Error for MissingPacketInfo {}
+
+/// An std::io::Error compatible error type expressing that a datagram was received from something
+/// other than an IPv4 or IPv6 address
+#[derive(Debug)]
+struct UnexpectedAddress;
+
+impl core::fmt::Display for UnexpectedAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Unexpected address type")
+    }
+}
+
+impl std::error::Error for UnexpectedAddress {}
+
+/// Err out unless all of the data was sent.
+pub(crate) fn check_sent(sent_len: usize, data: &[u8]) -> Result<(), Error> {
+    if sent_len != data.len() {
+        return Err(Error::new(std::io::ErrorKind::WriteZero, IncompleteSend));
+    }
+    Ok(())
+}
+
+/// Check that a socket bound to `bound` can send from `local`.
+///
+/// A port of 0 stands for the bound port, and an unspecified IP address lets the operating system
+/// pick the source address.
+fn check_local(bound: std::net::SocketAddr, local: std::net::SocketAddr) -> Result<(), Error> {
+    let mismatch = |reason| {
+        Error::new(
+            std::io::ErrorKind::InvalidInput,
+            LocalAddressMismatch(reason),
+        )
+    };
+    if local.port() != 0 && local.port() != bound.port() {
+        return Err(mismatch("port differs from the bound port"));
+    }
+    if local.is_ipv4() != bound.is_ipv4() {
+        return Err(mismatch("address family differs from the bound address"));
+    }
+    if !is_wildcard(bound.ip()) && !is_wildcard(local.ip()) && local.ip() != bound.ip() {
+        return Err(mismatch("address differs from the bound address"));
+    }
+    Ok(())
+}
+
+/// Send a datagram from the given local address, after checking that the socket bound to `bound`
+/// can send from there, and set the given metadata on it.
+///
+/// Packet info is used to select the local address only if the socket is bound to an unspecified
+/// address (in which case it has packet info enabled), and a concrete local address is requested.
+pub(crate) async fn send_from(
+    socket: &async_io::Async<std::net::UdpSocket>,
+    bound: std::net::SocketAddr,
+    interfaces: &Ipv4Interfaces,
+    local: std::net::SocketAddr,
+    remote: std::net::SocketAddr,
+    metadata: &Metadata,
+    data: &[u8],
+) -> Result<(), Error> {
+    let Some(control) = send_control(bound, interfaces, local, remote, metadata)? else {
+        let sent_len = socket
+            .send_to(data, remote)
+            .await
+            .map_err(|e| crate::pmtu::classify(e, socket))?;
+        return check_sent(sent_len, data);
+    };
+
+    socket
+        .write_with(|s| {
+            let sent_len = metadata::sendmsg(s.as_raw_fd(), data, Some(remote), &control)?;
+            check_sent(sent_len, data)
+        })
+        .await
+        .map_err(|e| crate::pmtu::classify(e, socket))
+}
+
+/// Check that a socket bound to `bound` can send from `local`, and assemble the control messages
+/// for sending from there to `remote` with the given metadata (if any are needed).
+pub(crate) fn send_control(
+    bound: std::net::SocketAddr,
+    interfaces: &Ipv4Interfaces,
+    local: std::net::SocketAddr,
+    remote: std::net::SocketAddr,
+    metadata: &Metadata,
+) -> Result<Option<metadata::Control>, Error> {
+    check_local(bound, local)?;
+
+    let local_pktinfo = if is_wildcard(bound.ip()) && !is_wildcard(local.ip()) {
+        Some(packet_info(interfaces, local, remote)?)
+    } else {
+        None
+    };
+
+    Ok(metadata::control(local_pktinfo, remote, metadata))
+}
+
+/// Build the packet info that selects the given local address when sending to `remote`.
+fn packet_info(
+    interfaces: &Ipv4Interfaces,
+    local: std::net::SocketAddr,
+    remote: std::net::SocketAddr,
+) -> Result<metadata::PacketInfo, Error> {
+    Ok(match remote {
+        std::net::SocketAddr::V6(_) => {
+            metadata::PacketInfo::V6(conversion::SocketAddr::from(local).try_into()?)
+        }
+        std::net::SocketAddr::V4(_) => {
+            let local_ip = conversion::IpAddr::from(local.ip());
+            let mut local_pktinfo: nix::libc::in_pktinfo = local_ip.try_into()?;
+            if let std::net::IpAddr::V4(local_ip) = local_ip.into() {
+                local_pktinfo.ipi_ifindex = interfaces.0.get(&local_ip).copied().unwrap_or(0) as _;
+            }
+            metadata::PacketInfo::V4(local_pktinfo)
+        }
+    })
+}
+
+/// Receive a datagram along with all enabled control messages, and report its local address,
+/// remote address and everything else that was received.
+pub(crate) async fn receive_with_control(
+    socket: &async_io::Async<std::net::UdpSocket>,
+    interfaces: &mut Ipv4Interfaces,
+    buffer: &mut [u8],
+    bound: std::net::SocketAddr,
+) -> Result<
+    (
+        std::net::SocketAddr,
+        std::net::SocketAddr,
+        metadata::Received,
+    ),
+    Error,
+> {
+    let mut received = socket
+        .read_with(|s| metadata::recvmsg(s.as_raw_fd(), buffer))
+        .await?;
+
+    let (local, remote) = addresses(&mut received, interfaces, bound)?;
+    Ok((local, remote, received))
+}
+
+/// Take the local and remote address out of a received datagram.
+///
+/// The local address is taken from the packet info if the socket bound to `bound` has it enabled,
+/// and is `bound` otherwise.
+pub(crate) fn addresses(
+    received: &mut metadata::Received,
+    interfaces: &mut Ipv4Interfaces,
+    bound: std::net::SocketAddr,
+) -> Result<(std::net::SocketAddr, std::net::SocketAddr), Error> {
+    let local = match received.packet_info.take() {
+        None if !is_wildcard(bound.ip()) => bound,
+        packet_info => local_address(packet_info, bound.port(), interfaces)?,
+    };
+    let remote = remote_address(received.remote.take())?;
+    Ok((local, remote))
+}
+
+/// Receive a datagram through a socket with packet info enabled, and report its length, local
+/// address and remote address.
+///
+/// This receives through the same control message space as the extended receive methods, so that
+/// the packet info is not cut off by any other control messages enabled on the socket.
+async fn receive_with_pktinfo(
+    socket: &async_io::Async<std::net::UdpSocket>,
+    interfaces: &mut Ipv4Interfaces,
+    buffer: &mut [u8],
+    bound: std::net::SocketAddr,
+) -> Result<(usize, std::net::SocketAddr, std::net::SocketAddr), Error> {
+    let (local, remote, received) = receive_with_control(socket, interfaces, buffer, bound).await?;
+    Ok((received.length, local, remote))
+}
+
+/// Find the local address of a received datagram in its control messages.
+fn local_address(
+    cmsgs: impl IntoIterator<Item = nix::sys::socket::ControlMessageOwned>,
+    port: u16,
+    interfaces: &mut Ipv4Interfaces,
+) -> Result<std::net::SocketAddr, Error> {
+    for cmsg in cmsgs {
+        match cmsg {
+            nix::sys::socket::ControlMessageOwned::Ipv6PacketInfo(pi) => {
+                return Ok(conversion::SocketAddr::from_pktinfo6(pi, port).into());
+            }
+            nix::sys::socket::ControlMessageOwned::Ipv4PacketInfo(pi) => {
+                let ip = conversion::IpAddr::from(pi);
+                if let std::net::IpAddr::V4(ip) = ip.into() {
+                    interfaces.0.insert(ip, pi.ipi_ifindex as _);
+                }
+                return Ok(std::net::SocketAddr::new(ip.into(), port));
+            }
+            _ => (),
+        }
+    }
+    Err(Error::new(
+        std::io::ErrorKind::InvalidData,
+        MissingPacketInfo,
+    ))
+}
+
+/// Convert the remote address reported by `recvmsg`.
+pub(crate) fn remote_address(
+    remote: Option<nix::sys::socket::SockaddrStorage>,
+) -> Result<std::net::SocketAddr, Error> {
+    let unexpected = || Error::new(std::io::ErrorKind::InvalidData, UnexpectedAddress);
+    let remote = remote.ok_or_else(unexpected)?;
+    // Taking this step on foot due to https://github.com/nix-rust/nix/issues/1754
+    let remote = match (remote.as_sockaddr_in6(), remote.as_sockaddr_in()) {
+        (Some(remote), None) => std::net::SocketAddr::V6(std::net::SocketAddrV6::new(
+            remote.ip(),
+            remote.port(),
+            remote.flowinfo(),
+            remote.scope_id(),
+        )),
+        (None, Some(remote)) => std::net::SocketAddr::V4(std::net::SocketAddrV4::new(
+            remote.ip().into(),
+            remote.port(),
+        )),
+        _ => return Err(unexpected()),
+    };
+
+    Ok(remote)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn incomplete_send() {
+        let err = check_sent(3, b"ping").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WriteZero);
+        check_sent(4, b"ping").unwrap();
+    }
+
+    #[test]
+    fn missing_pktinfo() {
+        let mut interfaces = Ipv4Interfaces::default();
+        let err = local_address([], 42, &mut interfaces).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unexpected_address() {
+        let err = remote_address(None).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
 
-        // We could probably shorten things by going more directly from SockaddrLike
-        let remote = conversion::SocketAddr::from(remote).into();
-        Ok((length, local, remote))
+        let unix = std::os::unix::net::UnixDatagram::unbound().unwrap();
+        let unix = nix::sys::socket::getsockname(unix.as_raw_fd()).unwrap();
+        let err = remote_address(Some(unix)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
     }
 }
@@ -0,0 +1,904 @@
+//! Completion based backend on Linux's io_uring
+//!
+//! The [`Stack`] of this module implements the same traits as the crate's main [`crate::Stack`],
+//! but rather than waiting for a socket to become ready and then performing a system call, it
+//! submits each operation to an io_uring, and is woken when the kernel reports its completion.
+//!
+//! The ring is driven by [`block_on()`], which is the executor for this backend: All operations
+//! need to be polled from within it, and fail with an error elsewhere. Consequently, the futures of
+//! this backend are not `Send`. Futures of other libraries can still be run inside [`block_on()`];
+//! wakeups from other threads interrupt the wait on the ring.
+//!
+//! As the kernel may access buffers until an operation completed (even if its future was dropped
+//! earlier), operations own their buffers, and data is copied from and to the buffers passed in
+//! through the embedded-nal-async traits. Each socket keeps its buffers for its next operation, so
+//! that operations do not allocate. When a future is dropped before the operation completed, the
+//! operation is cancelled, and its buffers are freed once the kernel is done with them.
+//!
+//! The ring is set up to run the kernel's completion work only when the executor waits for it,
+//! falling back to a plain ring on kernels before 6.1 that do not support this.
+//!
+//! Sockets are created and configured just as in the main backend, so the [`Stack`] can be
+//! created from a [`crate::Stack`] to use its configuration.
+//!
+//! # Performance
+//!
+//! This backend does not (yet) deliver a throughput gain over the main backend. The
+//! `udp_roundtrip` benchmark (64 byte datagrams over loopback, single threaded, on a single-CPU
+//! Linux 6.18 VM) measured it at:
+//!
+//! * 1 client/server pair: 5.5–8.0µs per round trip, against 4.2–4.9µs (25–60% slower)
+//! * 8 pairs: 4.1–5.9µs, against 4.2–4.8µs (from 20% slower to 5% faster)
+//! * 64 pairs: 3.8–4.5µs, against 4.2–5.3µs (5–15% faster)
+//!
+//! Only with many operations in flight is it on par or slightly ahead; the kernel's cost per
+//! operation dominates, and batching submissions saves little of it. Experiments with multishot
+//! receives and registered file descriptors did not change that. Use it where completion based
+//! I/O is needed, not for speed.
+//!
+//! ```
+//! use embedded_nal_async::{ConnectedUdp, UdpStack, UnconnectedUdp};
+//!
+//! std_embedded_nal_async::uring::block_on(async {
+//!     let stack = std_embedded_nal_async::uring::Stack::default();
+//!     let (server_addr, mut server) = stack.bind_single("127.0.0.1:0".parse().unwrap()).await?;
+//!     let (_, mut client) = stack.connect(server_addr).await?;
+//!
+//!     client.send(b"ping").await?;
+//!     let mut buffer = [0; 16];
+//!     let (len, _, _) = server.receive_into(&mut buffer).await?;
+//!     assert_eq!(&buffer[..len], b"ping");
+//!     Ok::<_, std::io::Error>(())
+//! })
+//! .unwrap()
+//! .unwrap();
+//! ```
+
+use crate::metadata::{self, Metadata, ReceiveSpace};
+use crate::udp::{self, Ipv4Interfaces};
+use io_uring::{opcode, squeue, types::Fd, IoUring};
+use nix::libc;
+use nix::sys::socket::SockaddrStorage;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::io::{self, Error};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::pin::{pin, Pin};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+/// Number of submission queue entries of each thread's ring
+const RING_ENTRIES: u32 = 256;
+
+/// User data of operations whose completion nothing waits for (cancellations)
+const UNTRACKED: u64 = u64::MAX;
+/// User data of the read on the executor's eventfd
+const WAKEUP: u64 = u64::MAX - 1;
+
+/// An std::io::Error compatible error type expressing that an operation was started outside of
+/// [`block_on()`], or that [`block_on()`] was entered twice on a thread
+#[derive(Debug)]
+struct NoDriver(&'static str);
+
+impl core::fmt::Display for NoDriver {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "io_uring driver unavailable: {}", self.0)
+    }
+}
+
+impl std::error::Error for NoDriver {}
+
+/// An std::io::Error compatible error type expressing that the submission queue stayed full even
+/// after its entries were handed to the kernel
+#[derive(Debug)]
+struct QueueFull;
+
+impl core::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "io_uring submission queue is full")
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+thread_local! {
+    static DRIVER: RefCell<Option<Rc<Driver>>> = const { RefCell::new(None) };
+}
+
+/// The driver of the thread the caller runs in, if it is inside [`block_on()`]
+fn current() -> Result<Rc<Driver>, Error> {
+    DRIVER
+        .with(|driver| driver.borrow().clone())
+        .ok_or_else(|| Error::new(io::ErrorKind::Unsupported, NoDriver("not inside block_on")))
+}
+
+/// Convert a completion result into the number of bytes it reports.
+fn completed(result: i32) -> Result<usize, Error> {
+    match result {
+        result if result < 0 => Err(Error::from_raw_os_error(-result)),
+        result => Ok(result as usize),
+    }
+}
+
+/// State of an operation submitted to the ring
+enum Slot {
+    Vacant,
+    Submitted(Option<Waker>),
+    Completed(i32),
+    /// The future was dropped before completion; the operation is being cancelled, and the
+    /// resources it points to are kept until the kernel is done with them.
+    Abandoned(Box<dyn Any>),
+}
+
+/// Operations in flight, indexed by the user data of their submissions
+///
+/// Slots are reused once the kernel reported an operation's completion, which happens only after
+/// any cancellation of it was handed to the kernel (and thus processed, as cancellations are
+/// processed during submission).
+#[derive(Default)]
+struct Slots {
+    slots: Vec<Slot>,
+    vacant: Vec<usize>,
+}
+
+impl Slots {
+    fn insert(&mut self) -> usize {
+        match self.vacant.pop() {
+            Some(key) => {
+                self.slots[key] = Slot::Submitted(None);
+                key
+            }
+            None => {
+                self.slots.push(Slot::Submitted(None));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    fn remove(&mut self, key: usize) -> Slot {
+        self.vacant.push(key);
+        core::mem::replace(&mut self.slots[key], Slot::Vacant)
+    }
+}
+
+struct Driver {
+    ring: RefCell<IoUring>,
+    ops: RefCell<Slots>,
+    /// Wakers of completed operations; kept to reuse its allocation across turns
+    woken: Cell<Vec<Waker>>,
+    /// The executor's eventfd, through which other threads interrupt the wait on the ring
+    wakeup_fd: RawFd,
+    /// Buffer of the read on `wakeup_fd`; a raw pointer as the kernel may write to it at any time
+    /// while `wakeup_armed`
+    wakeup_buffer: *mut u64,
+    wakeup_armed: Cell<bool>,
+}
+
+impl Driver {
+    fn new(wakeup_fd: RawFd) -> Result<Self, Error> {
+        // Only one thread ever submits, and completions are only ever waited for, so the kernel
+        // can defer its work until then. Kernels before 6.1 do not support that.
+        let ring = match IoUring::builder()
+            .setup_single_issuer()
+            .setup_defer_taskrun()
+            .build(RING_ENTRIES)
+        {
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => IoUring::new(RING_ENTRIES)?,
+            ring => ring?,
+        };
+        Ok(Self {
+            ring: RefCell::new(ring),
+            ops: Default::default(),
+            woken: Default::default(),
+            wakeup_fd,
+            wakeup_buffer: Box::into_raw(Box::new(0)),
+            wakeup_armed: Cell::new(false),
+        })
+    }
+
+    /// Queue an entry, handing queued entries to the kernel first if the queue is full.
+    ///
+    /// # Safety
+    ///
+    /// Everything the entry points to needs to stay valid and in place until its completion.
+    unsafe fn push(&self, entry: &squeue::Entry) -> Result<(), Error> {
+        let mut ring = self.ring.borrow_mut();
+        // SAFETY: As promised by the caller
+        if unsafe { ring.submission().push(entry) }.is_ok() {
+            return Ok(());
+        }
+        ring.submit()?;
+        // SAFETY: As promised by the caller
+        let pushed = unsafe { ring.submission().push(entry) };
+        pushed.map_err(|_| Error::new(io::ErrorKind::WouldBlock, QueueFull))
+    }
+
+    /// Submit queued entries, wait for at least one completion, and dispatch all completions.
+    fn turn(&self) -> Result<(), Error> {
+        if !self.wakeup_armed.get() {
+            let entry = opcode::Read::new(Fd(self.wakeup_fd), self.wakeup_buffer as *mut u8, 8)
+                .build()
+                .user_data(WAKEUP);
+            // SAFETY: The buffer is only freed when the read is not armed, and the eventfd
+            // outlives the driver.
+            unsafe { self.push(&entry)? };
+            self.wakeup_armed.set(true);
+        }
+
+        let mut woken = self.woken.take();
+        {
+            let mut ring = self.ring.borrow_mut();
+            match ring.submit_and_wait(1) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                result => {
+                    result?;
+                }
+            }
+            let mut ops = self.ops.borrow_mut();
+            for entry in ring.completion() {
+                match entry.user_data() {
+                    UNTRACKED => (),
+                    WAKEUP => self.wakeup_armed.set(false),
+                    key => {
+                        let key = key as usize;
+                        match ops.slots.get_mut(key) {
+                            Some(Slot::Submitted(waker)) => {
+                                woken.extend(waker.take());
+                                ops.slots[key] = Slot::Completed(entry.result());
+                            }
+                            // The kernel is done with the resources, which are freed here.
+                            Some(Slot::Abandoned(_)) => drop(ops.remove(key)),
+                            // Completions that belong to no operation in flight are not expected,
+                            // and there is nobody to report them to.
+                            _ => (),
+                        }
+                    }
+                }
+            }
+        }
+
+        // Wakers run without any borrows held, as they might poll right away
+        for waker in woken.drain(..) {
+            waker.wake();
+        }
+        self.woken.set(woken);
+        Ok(())
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        // Operations still in flight may be written to by the kernel even after the ring is
+        // closed, so their resources are leaked rather than freed. (Operations that are not
+        // abandoned keep the driver alive.)
+        for slot in self.ops.get_mut().slots.drain(..) {
+            if let Slot::Abandoned(resources) = slot {
+                core::mem::forget(resources);
+            }
+        }
+        if !self.wakeup_armed.get() {
+            // SAFETY: Created through Box::into_raw, and no read is pending on it.
+            drop(unsafe { Box::from_raw(self.wakeup_buffer) });
+        }
+    }
+}
+
+/// Resources of a socket's operations, reused from one operation to the next
+///
+/// They are boxed so that they stay in place while the kernel uses them. Only when an operation is
+/// abandoned, its resources stay with the driver until the kernel is done with them, and the next
+/// operation allocates new ones.
+struct Reusable<R>(Option<Box<R>>);
+
+impl<R> Default for Reusable<R> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<R: Default> Reusable<R> {
+    fn take(&mut self) -> Box<R> {
+        self.0.take().unwrap_or_default()
+    }
+}
+
+impl<R> Reusable<R> {
+    fn put(&mut self, resources: Box<R>) {
+        self.0 = Some(resources);
+    }
+}
+
+/// An operation on the ring of the current thread, completing with its result and its resources
+struct Op<R: 'static> {
+    driver: Rc<Driver>,
+    key: usize,
+    /// The resources the submission points to, until they are returned on completion
+    resources: Option<Box<R>>,
+}
+
+impl<R: 'static> Op<R> {
+    /// Submit the entry built from the resources.
+    ///
+    /// # Safety
+    ///
+    /// The entry may only point into memory owned by the resources that does not move when the
+    /// resources' box does (or into memory that outlives the operation otherwise).
+    unsafe fn submit(
+        mut resources: Box<R>,
+        entry: impl FnOnce(&mut R) -> squeue::Entry,
+    ) -> Result<Self, Error> {
+        let driver = current()?;
+        let key = driver.ops.borrow_mut().insert();
+        let entry = entry(&mut resources).user_data(key as u64);
+        // SAFETY: The resources are kept alive in their box until the completion, as promised
+        // by the caller, either in the Op or in the driver's slots.
+        if let Err(e) = unsafe { driver.push(&entry) } {
+            driver.ops.borrow_mut().remove(key);
+            return Err(e);
+        }
+        Ok(Self {
+            driver,
+            key,
+            resources: Some(resources),
+        })
+    }
+}
+
+impl<R: 'static> Future for Op<R> {
+    type Output = (i32, Box<R>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut ops = this.driver.ops.borrow_mut();
+        match &mut ops.slots[this.key] {
+            Slot::Submitted(Some(waker)) if waker.will_wake(cx.waker()) => Poll::Pending,
+            Slot::Submitted(waker) => {
+                *waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Slot::Completed(result) => {
+                let result = *result;
+                ops.remove(this.key);
+                let resources = this
+                    .resources
+                    .take()
+                    .expect("Completed operations are only polled until ready");
+                Poll::Ready((result, resources))
+            }
+            // Operations keep their slot until they are done, so this is not expected; the slot
+            // is not this operation's, and is left alone.
+            _ => {
+                let resources = this
+                    .resources
+                    .take()
+                    .expect("Completed operations are only polled until ready");
+                Poll::Ready((-libc::ECANCELED, resources))
+            }
+        }
+    }
+}
+
+impl<R: 'static> Drop for Op<R> {
+    fn drop(&mut self) {
+        let Some(resources) = self.resources.take() else {
+            return;
+        };
+        let mut ops = self.driver.ops.borrow_mut();
+        // A completed operation is done with its resources; any other is cancelled, and keeps them
+        // until the kernel is done.
+        if let Slot::Completed(_) = ops.slots[self.key] {
+            ops.remove(self.key);
+            return;
+        }
+        ops.slots[self.key] = Slot::Abandoned(resources);
+        drop(ops);
+        let entry = opcode::AsyncCancel::new(self.key as u64)
+            .build()
+            .user_data(UNTRACKED);
+        // SAFETY: Cancellations do not point to any memory. If the cancellation can not be
+        // queued, the operation completes on its own eventually (or is leaked with the driver).
+        let _ = unsafe { self.driver.push(&entry) };
+    }
+}
+
+/// Waker of [`block_on()`]
+struct Notifier {
+    woken: AtomicBool,
+    eventfd: OwnedFd,
+    thread: std::thread::ThreadId,
+}
+
+impl std::task::Wake for Notifier {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // The executor's own thread checks the flag before waiting on the ring; only other threads
+        // need to interrupt the wait.
+        if !self.woken.swap(true, Ordering::AcqRel) && std::thread::current().id() != self.thread {
+            let value = 1u64;
+            // SAFETY: Writing 8 bytes from a valid u64 to an eventfd. A failure can only mean
+            // that the counter is saturated, in which case the executor is awake anyway.
+            unsafe {
+                libc::write(
+                    self.eventfd.as_raw_fd(),
+                    &value as *const u64 as *const _,
+                    8,
+                )
+            };
+        }
+    }
+}
+
+/// Run a future to completion on the current thread, driving the thread's io_uring.
+///
+/// All operations of this module's [`Stack`] and its sockets need to run inside this. An error is
+/// only returned if the ring can not be set up or driven (eg. when io_uring is unavailable or
+/// disabled, or when called from within another `block_on()`).
+pub fn block_on<F: Future>(future: F) -> Result<F::Output, Error> {
+    if DRIVER.with(|driver| driver.borrow().is_some()) {
+        return Err(Error::new(
+            io::ErrorKind::Unsupported,
+            NoDriver("block_on is already running on this thread"),
+        ));
+    }
+
+    // SAFETY: eventfd has no memory safety preconditions.
+    let eventfd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if eventfd == -1 {
+        return Err(Error::last_os_error());
+    }
+    let notifier = Arc::new(Notifier {
+        woken: AtomicBool::new(true),
+        // SAFETY: The file descriptor was just created and is not owned elsewhere.
+        eventfd: unsafe { OwnedFd::from_raw_fd(eventfd) },
+        thread: std::thread::current().id(),
+    });
+    let driver = Rc::new(Driver::new(eventfd)?);
+
+    /// Removes the driver from the thread when block_on is left, even through a panic
+    struct Installed;
+    impl Drop for Installed {
+        fn drop(&mut self) {
+            DRIVER.with(|driver| driver.borrow_mut().take());
+        }
+    }
+    DRIVER.with(|current| *current.borrow_mut() = Some(driver.clone()));
+    let _installed = Installed;
+
+    let waker = Waker::from(notifier.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        while notifier.woken.swap(false, Ordering::AcqRel) {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return Ok(output);
+            }
+        }
+        driver.turn()?;
+    }
+}
+
+/// The operating system's network stack accessed through io_uring, implementing the same traits
+/// as [`crate::Stack`]
+///
+/// Its operations need to run inside [`block_on()`].
+#[derive(Clone, Default)]
+pub struct Stack {
+    inner: crate::Stack,
+}
+
+impl From<crate::Stack> for Stack {
+    /// Use the configuration of a stack built through [`crate::Stack::builder()`].
+    fn from(inner: crate::Stack) -> Self {
+        Self { inner }
+    }
+}
+
+impl Stack {
+    /// List the host's network interfaces, see [`crate::Stack::interfaces()`].
+    ///
+    /// This does not need to run inside [`block_on()`].
+    pub fn interfaces(&self) -> Result<Vec<crate::Interface>, Error> {
+        self.inner.interfaces()
+    }
+}
+
+/// Buffers of the operations of a datagram socket or a TCP connection, along with the header
+/// describing them for sending or receiving
+struct Message {
+    /// Data to send or received data; its capacity is reused, and never initialized beforehand
+    data: Vec<u8>,
+    iov: libc::iovec,
+    msg: libc::msghdr,
+    remote: Option<SockaddrStorage>,
+    control: metadata::Control,
+    space: ReceiveSpace,
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self {
+            data: Vec::new(),
+            iov: metadata::iovec(&[]),
+            // SAFETY: msghdr is plain old data, for which all zeros is a valid value.
+            msg: unsafe { core::mem::zeroed() },
+            remote: None,
+            control: Default::default(),
+            space: ReceiveSpace::new(),
+        }
+    }
+}
+
+impl Message {
+    /// Prepare the data for receiving up to `len` bytes, and describe it in `iov`.
+    fn receiving(&mut self, len: usize) {
+        self.data.clear();
+        self.data.reserve(len);
+        self.iov = libc::iovec {
+            iov_base: self.data.as_mut_ptr() as *mut _,
+            iov_len: len,
+        };
+    }
+
+    /// Copy the first `length` received bytes (as far as they fit) into the buffer.
+    ///
+    /// # Safety
+    ///
+    /// A receive into the prepared data must have reported `length` bytes.
+    unsafe fn received(&mut self, length: usize, buffer: &mut [u8]) {
+        let copied = length.min(buffer.len());
+        // SAFETY: The kernel initialized the received bytes, which are within the capacity
+        // reserved for the buffer's length.
+        unsafe { self.data.set_len(copied) };
+        buffer[..copied].copy_from_slice(&self.data);
+    }
+}
+
+/// Send data on a connected socket.
+async fn send(fd: RawFd, message: &mut Reusable<Message>, data: &[u8]) -> Result<usize, Error> {
+    let mut resources = message.take();
+    resources.data.clear();
+    resources.data.extend_from_slice(data);
+    // SAFETY: The entry points into the vector's heap allocation.
+    let op = unsafe {
+        Op::submit(resources, |r| {
+            opcode::Send::new(Fd(fd), r.data.as_ptr(), r.data.len() as _).build()
+        })?
+    };
+    let (result, resources) = op.await;
+    message.put(resources);
+    completed(result)
+}
+
+/// Receive into the buffer from a connected socket, with flags like those of `recv`.
+async fn recv(
+    fd: RawFd,
+    message: &mut Reusable<Message>,
+    buffer: &mut [u8],
+    flags: libc::c_int,
+) -> Result<usize, Error> {
+    let mut resources = message.take();
+    resources.receiving(buffer.len());
+    // SAFETY: The entry points into the vector's heap allocation.
+    let op = unsafe {
+        Op::submit(resources, |r| {
+            opcode::Recv::new(Fd(fd), r.iov.iov_base as *mut u8, r.iov.iov_len as _)
+                .flags(flags)
+                .build()
+        })?
+    };
+    let (result, mut resources) = op.await;
+    let length = completed(result);
+    if let Ok(length) = length {
+        // SAFETY: The receive operation succeeded with that length.
+        unsafe { resources.received(length, buffer) };
+    }
+    message.put(resources);
+    length
+}
+
+impl embedded_nal_async::UdpStack for Stack {
+    type Error = Error;
+    type Connected = ConnectedSocket;
+    type UniquelyBound = UnconnectedSocket;
+    type MultiplyBound = UnconnectedSocket;
+
+    async fn connect_from(
+        &self,
+        local: embedded_nal_async::SocketAddr,
+        remote: embedded_nal_async::SocketAddr,
+    ) -> Result<(embedded_nal_async::SocketAddr, Self::Connected), Self::Error> {
+        let v4_mapped = self.inner.config.v4_mapped;
+        let sock = crate::socket::udp_bind(
+            udp::to_socket(local, v4_mapped),
+            crate::SocketRole::UdpConnected,
+            &self.inner.config,
+        )?;
+
+        sock.connect(udp::to_socket(remote, v4_mapped))?;
+
+        let final_local = sock.local_addr()?;
+
+        Ok((
+            udp::to_api(final_local, v4_mapped),
+            ConnectedSocket::new(sock)?,
+        ))
+    }
+
+    async fn bind_single(
+        &self,
+        local: embedded_nal_async::SocketAddr,
+    ) -> Result<(embedded_nal_async::SocketAddr, Self::UniquelyBound), Self::Error> {
+        let v4_mapped = self.inner.config.v4_mapped;
+        let sock = crate::socket::udp_bind(
+            udp::to_socket(local, v4_mapped),
+            crate::SocketRole::UdpUniquelyBound,
+            &self.inner.config,
+        )?;
+
+        let final_local = sock.local_addr()?;
+        if udp::is_wildcard(final_local.ip()) {
+            udp::enable_pktinfo(&sock, final_local)?;
+        }
+
+        Ok((
+            udp::to_api(final_local, v4_mapped),
+            UnconnectedSocket::new(sock, final_local, v4_mapped)?,
+        ))
+    }
+
+    async fn bind_multiple(
+        &self,
+        local: embedded_nal_async::SocketAddr,
+    ) -> Result<Self::MultiplyBound, Self::Error> {
+        let v4_mapped = self.inner.config.v4_mapped;
+        let sock = crate::socket::udp_bind(
+            udp::to_socket(local, v4_mapped),
+            crate::SocketRole::UdpMultiplyBound,
+            &self.inner.config,
+        )?;
+
+        let local = sock.local_addr()?;
+        udp::enable_pktinfo(&sock, local)?;
+
+        UnconnectedSocket::new(sock, local, v4_mapped)
+    }
+}
+
+/// A connected UDP socket of the io_uring [`Stack`]
+pub struct ConnectedSocket {
+    socket: std::net::UdpSocket,
+    message: Reusable<Message>,
+}
+
+impl ConnectedSocket {
+    fn new(socket: std::net::UdpSocket) -> Result<Self, Error> {
+        // Like in the main backend, so that calls outside the ring never block
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            message: Default::default(),
+        })
+    }
+}
+
+impl embedded_nal_async::ConnectedUdp for ConnectedSocket {
+    type Error = Error;
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let sent_len = send(self.socket.as_raw_fd(), &mut self.message, data)
+            .await
+            .map_err(|e| crate::pmtu::classify(e, &self.socket))?;
+        udp::check_sent(sent_len, data)
+    }
+
+    async fn receive_into(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        // Truncating, but reporting the full length
+        recv(
+            self.socket.as_raw_fd(),
+            &mut self.message,
+            buffer,
+            libc::MSG_TRUNC,
+        )
+        .await
+    }
+}
+
+/// An unconnected UDP socket of the io_uring [`Stack`]
+///
+/// This serves as both the uniquely and the multiply bound socket: Sockets bound to an unspecified
+/// address use packet info to report and select local addresses, just like
+/// [`crate::MultiplyBoundSocket`] does.
+pub struct UnconnectedSocket {
+    socket: std::net::UdpSocket,
+    // Like in the main backend, this is V4-mapped in V4-mapped mode
+    bound_address: std::net::SocketAddr,
+    interfaces: Ipv4Interfaces,
+    v4_mapped: bool,
+    message: Reusable<Message>,
+}
+
+impl UnconnectedSocket {
+    fn new(
+        socket: std::net::UdpSocket,
+        bound_address: std::net::SocketAddr,
+        v4_mapped: bool,
+    ) -> Result<Self, Error> {
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            bound_address,
+            interfaces: Default::default(),
+            v4_mapped,
+            message: Default::default(),
+        })
+    }
+}
+
+impl embedded_nal_async::UnconnectedUdp for UnconnectedSocket {
+    type Error = Error;
+
+    async fn send(
+        &mut self,
+        local: embedded_nal_async::SocketAddr,
+        remote: embedded_nal_async::SocketAddr,
+        data: &[u8],
+    ) -> Result<(), Self::Error> {
+        let remote = udp::to_socket(remote, self.v4_mapped);
+        let control = udp::send_control(
+            self.bound_address,
+            &self.interfaces,
+            udp::to_socket(local, self.v4_mapped),
+            remote,
+            &Metadata::default(),
+        )?;
+        let mut resources = self.message.take();
+        resources.data.clear();
+        resources.data.extend_from_slice(data);
+        resources.control = control.unwrap_or_default();
+
+        let fd = self.socket.as_raw_fd();
+        // SAFETY: The header points into the boxed resources and the vector's heap allocation.
+        let op = unsafe {
+            Op::submit(resources, |r| {
+                r.iov = metadata::iovec(&r.data);
+                let remote = r.remote.insert(remote.into());
+                r.msg = metadata::send_header(&mut r.iov, Some(remote), &r.control);
+                opcode::SendMsg::new(Fd(fd), &r.msg).build()
+            })?
+        };
+        let (result, resources) = op.await;
+        self.message.put(resources);
+        let sent_len = completed(result).map_err(|e| crate::pmtu::classify(e, &self.socket))?;
+        udp::check_sent(sent_len, data)
+    }
+
+    async fn receive_into(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<
+        (
+            usize,
+            embedded_nal_async::SocketAddr,
+            embedded_nal_async::SocketAddr,
+        ),
+        Self::Error,
+    > {
+        let mut resources = self.message.take();
+        resources.receiving(buffer.len());
+
+        let fd = self.socket.as_raw_fd();
+        // SAFETY: The header points into the boxed resources and the vector's heap allocation.
+        let op = unsafe {
+            Op::submit(resources, |r| {
+                r.msg = r.space.header(&mut r.iov);
+                opcode::RecvMsg::new(Fd(fd), &mut r.msg)
+                    .flags(libc::MSG_TRUNC as _)
+                    .build()
+            })?
+        };
+        let (result, mut resources) = op.await;
+        let received = completed(result).map(|length| {
+            // SAFETY: The receive operation succeeded with that length.
+            unsafe { resources.received(length, buffer) };
+            // SAFETY: The receive operation succeeded.
+            unsafe { resources.space.parse(&resources.msg, length) }
+        });
+        self.message.put(resources);
+        let mut received = received?;
+        let (local, remote) =
+            udp::addresses(&mut received, &mut self.interfaces, self.bound_address)?;
+
+        Ok((
+            received.length,
+            udp::to_api(local, self.v4_mapped),
+            udp::to_api(remote, self.v4_mapped),
+        ))
+    }
+}
+
+impl embedded_nal_async::TcpConnect for Stack {
+    type Error = Error;
+
+    type Connection<'a> = TcpConnection;
+
+    async fn connect<'a>(
+        &'a self,
+        addr: embedded_nal_async::SocketAddr,
+    ) -> Result<Self::Connection<'a>, Error> {
+        let remote: std::net::SocketAddr = crate::conversion::SocketAddr::from(addr).into();
+        let stream =
+            std::net::TcpStream::from(crate::socket::tcp_socket(remote, &self.inner.config)?);
+        // Like in the main backend, so that calls outside the ring never block
+        stream.set_nonblocking(true)?;
+
+        let raw_fd = stream.as_raw_fd();
+        // SAFETY: The entry points into the boxed address.
+        let op = unsafe {
+            Op::submit(Box::new(SockaddrStorage::from(remote)), |remote| {
+                use nix::sys::socket::SockaddrLike;
+                opcode::Connect::new(Fd(raw_fd), remote.as_ptr(), remote.len()).build()
+            })?
+        };
+        completed(op.await.0)?;
+
+        Ok(TcpConnection {
+            stream,
+            message: Default::default(),
+        })
+    }
+}
+
+/// A TCP connection of the io_uring [`Stack`]
+pub struct TcpConnection {
+    stream: std::net::TcpStream,
+    message: Reusable<Message>,
+}
+
+impl embedded_io_async::ErrorType for TcpConnection {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for TcpConnection {
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        recv(self.stream.as_raw_fd(), &mut self.message, buffer, 0).await
+    }
+}
+
+impl embedded_io_async::Write for TcpConnection {
+    async fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+        send(self.stream.as_raw_fd(), &mut self.message, buffer).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        // Sent data is handed to the kernel right away
+        Ok(())
+    }
+}
+
+/// Name resolution is blocking in the operating system; it runs on a thread pool just like in
+/// [`crate::Stack`], whose results wake [`block_on()`].
+impl embedded_nal_async::Dns for Stack {
+    type Error = Error;
+
+    async fn get_host_by_name(
+        &self,
+        hostname: &str,
+        addr_type: embedded_nal_async::AddrType,
+    ) -> Result<embedded_nal_async::IpAddr, Self::Error> {
+        embedded_nal_async::Dns::get_host_by_name(&self.inner, hostname, addr_type).await
+    }
+
+    async fn get_host_by_address(
+        &self,
+        addr: embedded_nal_async::IpAddr,
+        result: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        embedded_nal_async::Dns::get_host_by_address(&self.inner, addr, result).await
+    }
+}
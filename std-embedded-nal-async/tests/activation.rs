@@ -0,0 +1,107 @@
+//! Tests for socket activation
+//!
+//! This fakes a service manager by placing sockets at the file descriptors the protocol
+//! prescribes. As that affects the whole process, this must be the only test in this file.
+
+use embedded_nal_async::UnconnectedUdp;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std_embedded_nal_async::activation;
+
+#[test]
+fn activated_sockets() {
+    let single = std::net::UdpSocket::bind("[::1]:0").unwrap();
+    let single_addr = single.local_addr().unwrap();
+    let multiple = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+    let multiple_port = multiple.local_addr().unwrap().port();
+    // Like a systemd socket unit with ListenDatagram=[::]:…
+    let dual = {
+        use nix::sys::socket::{bind, setsockopt, socket, sockopt, SockaddrIn6};
+        use nix::sys::socket::{AddressFamily, SockFlag, SockType};
+        let fd = socket(
+            AddressFamily::Inet6,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .unwrap();
+        setsockopt(&fd, sockopt::Ipv6V6Only, &false).unwrap();
+        let any: std::net::SocketAddrV6 = "[::]:0".parse().unwrap();
+        bind(fd.as_raw_fd(), &SockaddrIn6::from(any)).unwrap();
+        std::net::UdpSocket::from(fd)
+    };
+    let dual_port = dual.local_addr().unwrap().port();
+
+    // Moving them out of the way first, as the sockets may have been created at the target
+    // file descriptors
+    let dup_high = |fd| nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(10)).unwrap();
+    let high = [
+        dup_high(single.as_raw_fd()),
+        dup_high(multiple.as_raw_fd()),
+        dup_high(dual.as_raw_fd()),
+    ];
+    drop((single, multiple, dual));
+    for (target, high) in (3..).zip(high) {
+        nix::unistd::dup2(high, target).unwrap();
+        nix::unistd::close(high).unwrap();
+    }
+
+    std::env::set_var("LISTEN_PID", std::process::id().to_string());
+    std::env::set_var("LISTEN_FDS", "3");
+
+    let mut fds = activation::listen_fds().unwrap().into_iter();
+    assert!(std::env::var("LISTEN_FDS").is_err());
+    assert!(activation::listen_fds().unwrap().is_empty());
+
+    let single_fd = fds.next().unwrap();
+    let multiple_fd = fds.next().unwrap();
+    let dual_fd = fds.next().unwrap();
+    assert!(fds.next().is_none());
+
+    let not_a_socket = OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+    assert!(activation::bind_multiple(not_a_socket).is_err());
+    let tcp = OwnedFd::from(std::net::TcpListener::bind("[::1]:0").unwrap());
+    assert!(activation::bind_multiple(tcp).is_err());
+
+    async_std::task::block_on(async move {
+        let (local, mut single) = activation::bind_single(single_fd).unwrap();
+        assert_eq!(local, single_addr.to_string().parse().unwrap());
+
+        let client = std::net::UdpSocket::bind("[::1]:0").unwrap();
+        client.send_to(b"ping", single_addr).unwrap();
+        let mut buf = [0; 4];
+        let (_, local, remote) = single.receive_into(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        single.send(local, remote, b"pong").await.unwrap();
+        client.recv(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        let mut multiple = activation::bind_multiple(multiple_fd).unwrap();
+        let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .send_to(b"ping", ("127.0.0.1", multiple_port))
+            .unwrap();
+        let (_, local, remote) = multiple.receive_into(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!(
+            local,
+            format!("127.0.0.1:{}", multiple_port).parse().unwrap(),
+            "Packet info was not enabled"
+        );
+        multiple.send(local, remote, b"pong").await.unwrap();
+        client.recv(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // IPv4 traffic on a dual-stack socket is reported with IPv4 addresses
+        let mut dual = activation::bind_multiple(dual_fd).unwrap();
+        client.send_to(b"ping", ("127.0.0.1", dual_port)).unwrap();
+        let (_, local, remote) = dual.receive_into(&mut buf).await.unwrap();
+        assert_eq!(local, format!("127.0.0.1:{dual_port}").parse().unwrap());
+        assert_eq!(
+            remote,
+            client.local_addr().unwrap().to_string().parse().unwrap()
+        );
+        dual.send(local, remote, b"pong").await.unwrap();
+        client.recv(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+    });
+}
@@ -47,3 +47,99 @@ fn std_echov6() {
     let mut stack = std_embedded_nal_async::Stack::default();
     async_std::task::block_on(echo(&mut stack, "[::1]:4223"));
 }
+
+#[test]
+fn socket_hook() {
+    use embedded_nal_async::UdpStack;
+    use std::sync::{Arc, Mutex};
+    use std_embedded_nal_async::SocketRole;
+
+    let seen = Arc::new(Mutex::new(vec![]));
+    let hook_seen = seen.clone();
+    let mut stack = std_embedded_nal_async::Stack::builder()
+        .socket_hook(move |fd, role| {
+            // The socket is neither bound nor connected yet
+            let socket = std::net::UdpSocket::from(fd.try_clone_to_owned()?);
+            assert_eq!(socket.local_addr()?.port(), 0);
+            hook_seen.lock().unwrap().push(role);
+            Ok(())
+        })
+        .build();
+
+    async_std::task::block_on(async {
+        echo(&mut stack, "127.0.0.1:4251").await;
+
+        let local = "[::1]:0".parse().unwrap();
+        let (addr, _single) = stack.bind_single(local).await.unwrap();
+        let _multiple = stack.bind_multiple(local).await.unwrap();
+        let _connected = UdpStack::connect(&stack, addr).await.unwrap();
+
+        // Refused connections are reported after the hook ran
+        let err = TcpConnect::connect(&stack, "127.0.0.1:4248".parse().unwrap())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+    });
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            SocketRole::TcpConnection,
+            SocketRole::UdpUniquelyBound,
+            SocketRole::UdpMultiplyBound,
+            SocketRole::UdpConnected,
+            SocketRole::TcpConnection,
+        ]
+    );
+
+    // Errors from the hook fail the operation
+    let failing = std_embedded_nal_async::Stack::builder()
+        .socket_hook(|_, _| Err(std::io::ErrorKind::PermissionDenied.into()))
+        .build();
+    let err = async_std::task::block_on(failing.bind_single("[::1]:0".parse().unwrap()))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn bind_device() {
+    use embedded_nal_async::{ConnectedUdp, UdpStack, UnconnectedUdp};
+    use std::sync::{Arc, Mutex};
+
+    let devices = Arc::new(Mutex::new(vec![]));
+    let hook_devices = devices.clone();
+    let mut stack = std_embedded_nal_async::Stack::builder()
+        .bind_device("lo")
+        .socket_hook(move |fd, _| {
+            let device =
+                nix::sys::socket::getsockopt(&fd, nix::sys::socket::sockopt::BindToDevice)?;
+            hook_devices.lock().unwrap().push(device);
+            Ok(())
+        })
+        .build();
+
+    async_std::task::block_on(async {
+        echo(&mut stack, "[::1]:4252").await;
+
+        let (addr, mut server) = stack.bind_single("[::1]:0".parse().unwrap()).await.unwrap();
+        let (_, mut client) = UdpStack::connect(&stack, addr).await.unwrap();
+        client.send(b"ping").await.unwrap();
+        let mut buffer = [0; 4];
+        let (len, _, _) = server.receive_into(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..len], b"ping");
+    });
+
+    // nix reports the name including its terminating NUL
+    let devices = devices.lock().unwrap();
+    assert_eq!(devices.len(), 3);
+    for device in devices.iter() {
+        assert_eq!(device.to_str().unwrap().trim_end_matches('\0'), "lo");
+    }
+
+    let missing = std_embedded_nal_async::Stack::builder()
+        .bind_device("nonexistent0")
+        .build();
+    assert!(async_std::task::block_on(missing.bind_single("[::1]:0".parse().unwrap())).is_err());
+}
@@ -37,3 +37,863 @@ fn std_echov6() {
     let mut stack = std_embedded_nal_async::Stack::default();
     async_std::task::block_on(echo(&mut stack, "[::1]:4223"));
 }
+
+/// Like [echo], but with a uniquely bound server socket that is bound to the unspecified address
+async fn echo_single_wildcard(stack: &mut impl UdpStack, bind: &str, addr: &str) {
+    let bind: SocketAddr = bind.parse().unwrap();
+    let addr: SocketAddr = addr.parse().unwrap();
+
+    let (bound, mut servsock) = stack.bind_single(bind).await.unwrap();
+    assert_eq!(bound.ip(), bind.ip());
+    let addr = SocketAddr::new(addr.ip(), bound.port());
+    let (cli_local, mut clisock) = stack.connect(addr).await.unwrap();
+
+    clisock.send(b"ping").await.unwrap();
+    let mut buffer = [0u8; 10];
+    let (received, servaddr, server_cliaddr) = servsock.receive_into(&mut buffer).await.unwrap();
+    assert_eq!(received, 4);
+    assert_eq!(
+        servaddr, addr,
+        "Server reported the bound rather than the actual local address"
+    );
+    assert_eq!(server_cliaddr, cli_local);
+
+    servsock
+        .send(servaddr, server_cliaddr, b"pong")
+        .await
+        .unwrap();
+    let mut buffer = [0u8; 10];
+    let received = clisock.receive_into(&mut buffer).await.unwrap();
+    assert_eq!(&buffer[..received], b"pong");
+}
+
+#[test]
+fn std_echo_single_wildcardv4() {
+    let mut stack = std_embedded_nal_async::Stack::default();
+    async_std::task::block_on(echo_single_wildcard(&mut stack, "0.0.0.0:0", "127.0.0.1:0"));
+}
+
+#[test]
+fn std_echo_single_wildcardv6() {
+    let mut stack = std_embedded_nal_async::Stack::default();
+    async_std::task::block_on(echo_single_wildcard(&mut stack, "[::]:0", "[::1]:0"));
+}
+
+/// Find a link-local address of any interface (preferably the loopback interface), along with
+/// the interface's index.
+fn link_local_address() -> Option<(std::net::Ipv6Addr, u32)> {
+    let table = std::fs::read_to_string("/proc/net/if_inet6").ok()?;
+    let mut candidates: Vec<_> = table
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let addr = u128::from_str_radix(fields.first()?, 16).ok()?;
+            let ifindex = u32::from_str_radix(fields.get(1)?, 16).ok()?;
+            let name = *fields.get(5)?;
+            let addr = std::net::Ipv6Addr::from(addr);
+            ((addr.segments()[0] & 0xffc0) == 0xfe80).then_some((name != "lo", addr, ifindex))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+        .first()
+        .map(|(_, addr, ifindex)| (*addr, *ifindex))
+}
+
+#[test]
+fn std_echo_link_local() {
+    let Some((addr, ifindex)) = link_local_address() else {
+        eprintln!("No link-local address available, skipping test");
+        return;
+    };
+
+    async_std::task::block_on(async {
+        let stack = std_embedded_nal_async::Stack::default();
+
+        let mut servsock = stack
+            .bind_multiple("[::]:4224".parse().unwrap())
+            .await
+            .unwrap();
+        let addr: SocketAddr =
+            embedded_nal_async::SocketAddrV6::new(addr.octets().into(), 4224, 0, ifindex).into();
+        let (cli_local, mut clisock) = stack.connect(addr).await.unwrap();
+
+        clisock.send(b"ping").await.unwrap();
+        let mut buffer = [0u8; 10];
+        let (_, servaddr, server_cliaddr) = servsock.receive_into(&mut buffer).await.unwrap();
+        assert_eq!(servaddr, addr, "Interface index was not reported");
+        assert_eq!(server_cliaddr, cli_local);
+
+        servsock
+            .send(servaddr, server_cliaddr, b"pong")
+            .await
+            .unwrap();
+        let received = clisock.receive_into(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..received], b"pong");
+    });
+}
+
+#[test]
+fn interfaces() {
+    let stack = std_embedded_nal_async::Stack::default();
+    let interfaces = stack.interfaces().unwrap();
+
+    let lo = interfaces.iter().find(|i| i.name == "lo").unwrap();
+    assert!(lo.up && lo.loopback);
+    assert!(lo.addresses.contains(&"127.0.0.1".parse().unwrap()));
+    assert!(lo.addresses.contains(&"::1".parse().unwrap()));
+    // Each interface is only listed once, no matter how many addresses it has
+    assert_eq!(interfaces.iter().filter(|i| i.name == "lo").count(), 1);
+
+    let link_local = interfaces.iter().find_map(|i| {
+        let addr = i.addresses.iter().find_map(|a| match a {
+            embedded_nal_async::IpAddr::V6(a) if (a.segments()[0] & 0xffc0) == 0xfe80 => Some(*a),
+            _ => None,
+        })?;
+        Some((addr, i.index))
+    });
+    let Some((addr, ifindex)) = link_local else {
+        eprintln!("No link-local address available, skipping the interface index check");
+        return;
+    };
+
+    // The interface index lines up with the one reported for received datagrams
+    async_std::task::block_on(async {
+        let mut servsock = stack
+            .bind_multiple("[::]:4253".parse().unwrap())
+            .await
+            .unwrap();
+        let addr: SocketAddr = embedded_nal_async::SocketAddrV6::new(addr, 4253, 0, ifindex).into();
+        let (_, mut clisock) = stack.connect(addr).await.unwrap();
+
+        clisock.send(b"ping").await.unwrap();
+        let mut buffer = [0u8; 10];
+        let (_, servaddr, _) = servsock.receive_into(&mut buffer).await.unwrap();
+        let SocketAddr::V6(servaddr) = servaddr else {
+            panic!("Received on IPv4 address");
+        };
+        assert_eq!(servaddr.scope_id(), ifindex);
+    });
+}
+
+#[test]
+fn send_local_validation() {
+    async_std::task::block_on(async {
+        let stack = std_embedded_nal_async::Stack::default();
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let remote: SocketAddr = receiver.local_addr().unwrap().to_string().parse().unwrap();
+
+        let mut sock = stack
+            .bind_multiple("127.0.0.1:4225".parse().unwrap())
+            .await
+            .unwrap();
+
+        for wrong in ["127.0.0.1:4226", "127.0.0.2:4225", "[::1]:4225"] {
+            let err = sock
+                .send(wrong.parse().unwrap(), remote, b"wrong")
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{}", wrong);
+        }
+
+        for right in ["127.0.0.1:4225", "127.0.0.1:0", "0.0.0.0:0"] {
+            sock.send(right.parse().unwrap(), remote, b"right")
+                .await
+                .unwrap();
+            let mut buffer = [0u8; 10];
+            let (len, from) = receiver.recv_from(&mut buffer).unwrap();
+            assert_eq!(&buffer[..len], b"right");
+            assert_eq!(from, "127.0.0.1:4225".parse().unwrap());
+        }
+    });
+}
+
+/// Send an oversized datagram in either direction between a uniquely bound and a connected socket,
+/// and check that the full length is reported
+async fn truncation(stack: &mut impl UdpStack, bind: &str) {
+    let (servaddr, mut servsock) = stack.bind_single(bind.parse().unwrap()).await.unwrap();
+    let (cli_local, mut clisock) = stack.connect(servaddr).await.unwrap();
+
+    clisock.send(b"0123456789").await.unwrap();
+    let mut buffer = [0u8; 4];
+    let (received, _, _) = servsock.receive_into(&mut buffer).await.unwrap();
+    assert_eq!(received, 10);
+    assert_eq!(&buffer, b"0123");
+
+    servsock
+        .send(servaddr, cli_local, b"0123456789")
+        .await
+        .unwrap();
+    let received = clisock.receive_into(&mut buffer).await.unwrap();
+    assert_eq!(received, 10);
+    assert_eq!(&buffer, b"0123");
+}
+
+#[test]
+fn std_truncationv4() {
+    let mut stack = std_embedded_nal_async::Stack::default();
+    async_std::task::block_on(truncation(&mut stack, "127.0.0.1:0"));
+}
+
+#[test]
+fn std_truncationv6() {
+    let mut stack = std_embedded_nal_async::Stack::default();
+    async_std::task::block_on(truncation(&mut stack, "[::1]:0"));
+}
+
+#[test]
+fn send_mixed_family() {
+    async_std::task::block_on(async {
+        let stack = std_embedded_nal_async::Stack::default();
+        let mut sock = stack
+            .bind_multiple("[::]:4227".parse().unwrap())
+            .await
+            .unwrap();
+
+        // An IPv6 local address with an IPv4 remote address can not be expressed on the socket
+        let err = sock
+            .send(
+                "[::1]:4227".parse().unwrap(),
+                "127.0.0.1:4228".parse().unwrap(),
+                b"mixed",
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    });
+}
+
+#[test]
+fn v4_mapped_dual_stack() {
+    async_std::task::block_on(async {
+        let mapped = std_embedded_nal_async::Stack::builder()
+            .v4_mapped(true)
+            .build();
+        let plain = std_embedded_nal_async::Stack::default();
+
+        let mut servsock = mapped
+            .bind_multiple("[::]:4229".parse().unwrap())
+            .await
+            .unwrap();
+
+        for addr in ["127.0.0.1:4229", "[::1]:4229"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let (cli_local, mut clisock) = plain.connect(addr).await.unwrap();
+
+            clisock.send(b"ping").await.unwrap();
+            let mut buffer = [0u8; 10];
+            let (_, servaddr, server_cliaddr) = servsock.receive_into(&mut buffer).await.unwrap();
+            // Addresses are given out in their plain form, not as V4-mapped addresses
+            assert_eq!(servaddr, addr);
+            assert_eq!(server_cliaddr, cli_local);
+
+            servsock
+                .send(servaddr, server_cliaddr, b"pong")
+                .await
+                .unwrap();
+            let received = clisock.receive_into(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..received], b"pong");
+        }
+
+        // IPv4 addresses can be used with all kinds of sockets
+        let (single, _) = mapped
+            .bind_single("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(single.is_ipv4());
+        let (cli_local, _) = mapped.connect(single).await.unwrap();
+        assert!(cli_local.is_ipv4());
+    });
+}
+
+async fn multicast(stack: &std_embedded_nal_async::Stack, bind: &str, group: &str) {
+    let bind: SocketAddr = bind.parse().unwrap();
+    let group: SocketAddr = group.parse().unwrap();
+
+    let mut servsock = stack.bind_multiple(bind).await.unwrap();
+    servsock.join_multicast(group.ip(), 0).unwrap();
+    servsock.set_multicast_loop(true).unwrap();
+    servsock.set_multicast_hop_limit(1).unwrap();
+
+    // Not a connected socket, as that would not accept the response from a unicast address
+    let mut unspecified = bind;
+    unspecified.set_port(0);
+    let (cli_local, mut clisock) = stack.bind_single(unspecified).await.unwrap();
+    clisock.send(cli_local, group, b"ping").await.unwrap();
+    let mut buffer = [0u8; 10];
+    let (received, servaddr, server_cliaddr) = servsock.receive_into(&mut buffer).await.unwrap();
+    assert_eq!(&buffer[..received], b"ping");
+    assert_eq!(servaddr, group, "Group was not reported as local address");
+    assert_eq!(server_cliaddr.port(), cli_local.port());
+
+    // Responses from the group address are sent from a unicast address
+    servsock
+        .send(servaddr, server_cliaddr, b"pong")
+        .await
+        .unwrap();
+    let (received, _, cli_remote) = clisock.receive_into(&mut buffer).await.unwrap();
+    assert_eq!(&buffer[..received], b"pong");
+    assert_eq!(cli_remote.port(), group.port());
+    assert_ne!(cli_remote.ip(), group.ip());
+
+    servsock.leave_multicast(group.ip(), 0).unwrap();
+    // Leaving twice fails, as the membership is gone
+    assert!(servsock.leave_multicast(group.ip(), 0).is_err());
+}
+
+#[test]
+fn std_multicastv4() {
+    let stack = std_embedded_nal_async::Stack::default();
+    async_std::task::block_on(multicast(&stack, "0.0.0.0:4231", "239.255.42.99:4231"));
+}
+
+#[test]
+fn std_multicastv6() {
+    let stack = std_embedded_nal_async::Stack::default();
+    async_std::task::block_on(multicast(&stack, "[::]:4232", "[ff05::4232]:4232"));
+}
+
+#[test]
+fn multicast_memberships() {
+    async_std::task::block_on(async {
+        let mapped = std_embedded_nal_async::Stack::builder()
+            .v4_mapped(true)
+            .build();
+        let (_, sock) = mapped
+            .bind_single("[::]:4233".parse().unwrap())
+            .await
+            .unwrap();
+
+        // Both families' groups can be joined on a V4-mapped socket
+        let group4 = "232.1.2.3".parse().unwrap();
+        let source4 = "192.0.2.1".parse().unwrap();
+        let group6 = "ff35::4233".parse().unwrap();
+        let source6 = "2001:db8::1".parse().unwrap();
+        sock.join_multicast_source(group4, source4, 0).unwrap();
+        sock.join_multicast_source(group6, source6, 0).unwrap();
+        sock.leave_multicast_source(group4, source4, 0).unwrap();
+        sock.leave_multicast_source(group6, source6, 0).unwrap();
+
+        sock.set_multicast_interface(0).unwrap();
+        sock.set_multicast_hop_limit(8).unwrap();
+        sock.set_multicast_loop(false).unwrap();
+    });
+}
+
+async fn metadata(stack: &std_embedded_nal_async::Stack, server: &str, client: &str) {
+    use std_embedded_nal_async::{Ecn, Metadata};
+
+    let (servaddr, mut servsock) = stack.bind_single(server.parse().unwrap()).await.unwrap();
+    let mut clisock = stack.bind_multiple(client.parse().unwrap()).await.unwrap();
+
+    let mut sent = Metadata {
+        traffic_class: Some(0x28 << 2),
+        hop_limit: Some(42),
+    };
+    sent.set_ecn(Ecn::Ect0);
+    let mut buffer = [0u8; 10];
+    clisock
+        .send_with_metadata(client.parse().unwrap(), servaddr, b"ping", &sent)
+        .await
+        .unwrap();
+    let (length, local, remote, received) = servsock
+        .receive_into_with_metadata(&mut buffer)
+        .await
+        .unwrap();
+    assert_eq!(&buffer[..length], b"ping");
+    assert_eq!(local, servaddr);
+    assert_eq!(remote.ip(), servaddr.ip());
+    assert_eq!(received, sent);
+    assert_eq!(received.ecn(), Some(Ecn::Ect0));
+
+    // The plain receive path is unaffected by the enabled options
+    clisock
+        .send_with_metadata(client.parse().unwrap(), servaddr, b"pong", &sent)
+        .await
+        .unwrap();
+    let (length, local, _) = servsock.receive_into(&mut buffer).await.unwrap();
+    assert_eq!(&buffer[..length], b"pong");
+    assert_eq!(local, servaddr);
+}
+
+fn receive_metadata() -> std_embedded_nal_async::SocketOptions {
+    std_embedded_nal_async::SocketOptions::default().receive_metadata(true)
+}
+
+#[test]
+fn std_metadatav4() {
+    let stack = std_embedded_nal_async::Stack::builder()
+        .socket_options(receive_metadata())
+        .build();
+    async_std::task::block_on(metadata(&stack, "127.0.0.1:4234", "0.0.0.0:4235"));
+}
+
+#[test]
+fn std_metadatav6() {
+    let stack = std_embedded_nal_async::Stack::builder()
+        .socket_options(receive_metadata())
+        .build();
+    async_std::task::block_on(metadata(&stack, "[::1]:4236", "[::]:4237"));
+}
+
+#[test]
+fn std_metadata_v4_mapped() {
+    let stack = std_embedded_nal_async::Stack::builder()
+        .v4_mapped(true)
+        .socket_options(receive_metadata())
+        .build();
+    async_std::task::block_on(metadata(&stack, "127.0.0.1:4238", "[::]:4239"));
+}
+
+#[test]
+fn connected_metadata() {
+    use std_embedded_nal_async::{Ecn, Metadata};
+
+    async_std::task::block_on(async {
+        let stack = std_embedded_nal_async::Stack::builder()
+            .socket_options(receive_metadata())
+            .build();
+        let (servaddr, mut servsock) = stack
+            .bind_single("[::1]:4254".parse().unwrap())
+            .await
+            .unwrap();
+        let (cli_local, mut clisock) = stack.connect(servaddr).await.unwrap();
+
+        let mut sent = Metadata {
+            traffic_class: None,
+            hop_limit: Some(42),
+        };
+        sent.set_ecn(Ecn::Ect1);
+        clisock.send_with_metadata(b"ping", &sent).await.unwrap();
+        let mut buffer = [0u8; 10];
+        let (length, _, remote, received) = servsock
+            .receive_into_with_metadata(&mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..length], b"ping");
+        assert_eq!(remote, cli_local);
+        assert_eq!(received, sent);
+
+        servsock
+            .send_with_metadata(servaddr, remote, b"pong", &sent)
+            .await
+            .unwrap();
+        let (length, received) = clisock
+            .receive_into_with_metadata(&mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..length], b"pong");
+        assert_eq!(received, sent);
+    });
+}
+
+#[test]
+fn metadata_not_enabled() {
+    async_std::task::block_on(async {
+        let stack = std_embedded_nal_async::Stack::default();
+        let (servaddr, mut servsock) = stack
+            .bind_single("127.0.0.1:4255".parse().unwrap())
+            .await
+            .unwrap();
+        let (_, mut clisock) = stack.connect(servaddr).await.unwrap();
+        let mut buffer = [0u8; 10];
+
+        let err = servsock
+            .receive_into_with_metadata(&mut buffer)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        let err = clisock
+            .receive_into_with_metadata(&mut buffer)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        // Enabled later, it applies to datagrams arriving from then on
+        servsock.enable_metadata().unwrap();
+        clisock.send(b"ping").await.unwrap();
+        let (_, _, _, received) = servsock
+            .receive_into_with_metadata(&mut buffer)
+            .await
+            .unwrap();
+        assert!(received.hop_limit.is_some());
+    });
+}
+
+#[test]
+fn receive_timestamp() {
+    async_std::task::block_on(async {
+        let stack = std_embedded_nal_async::Stack::builder()
+            .socket_options(
+                std_embedded_nal_async::SocketOptions::default().receive_timestamps(true),
+            )
+            .build();
+        let mut servsock = stack
+            .bind_multiple("[::]:4240".parse().unwrap())
+            .await
+            .unwrap();
+        let (cli_local, mut clisock) = stack.connect("[::1]:4240".parse().unwrap()).await.unwrap();
+        let mut buffer = [0u8; 10];
+
+        let before = std::time::SystemTime::now();
+        clisock.send(b"ping").await.unwrap();
+        let (length, local, remote, timestamp) = servsock
+            .receive_into_with_timestamp(&mut buffer)
+            .await
+            .unwrap();
+        let after = std::time::SystemTime::now();
+        assert_eq!(&buffer[..length], b"ping");
+        assert_eq!(local, "[::1]:4240".parse().unwrap());
+        assert_eq!(remote, cli_local);
+        let timestamp = timestamp.expect("Timestamps were enabled before sending");
+        assert!(before <= timestamp && timestamp <= after);
+
+        // The timestamp does not crowd out the packet info of plain receives
+        clisock.send(b"pong").await.unwrap();
+        let (length, local, remote) = servsock.receive_into(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], b"pong");
+        assert_eq!(local, "[::1]:4240".parse().unwrap());
+        assert_eq!(remote, cli_local);
+
+        // Without the socket option, timestamps are enabled on the socket explicitly
+        let (servaddr, mut plain) = std_embedded_nal_async::Stack::default()
+            .bind_single("[::1]:0".parse().unwrap())
+            .await
+            .unwrap();
+        let err = plain
+            .receive_into_with_timestamp(&mut buffer)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        plain.enable_timestamps().unwrap();
+        let (_, mut sender) = stack.connect(servaddr).await.unwrap();
+        sender.send(b"ping").await.unwrap();
+        let (_, _, _, timestamp) = plain
+            .receive_into_with_timestamp(&mut buffer)
+            .await
+            .unwrap();
+        assert!(timestamp.is_some());
+    });
+}
+
+fn assert_send<T: Send>(_: &T) {}
+
+#[test]
+fn batch() {
+    async_std::task::block_on(async {
+        let stack = std_embedded_nal_async::Stack::default();
+        let mut servsock = stack
+            .bind_multiple("[::]:4241".parse().unwrap())
+            .await
+            .unwrap();
+        let addr: SocketAddr = "[::1]:4241".parse().unwrap();
+        let (cli_local, mut clisock) = stack.connect(addr).await.unwrap();
+
+        let sending = clisock.send_batch(&[b"one", b"two", b"three"]);
+        assert_send(&sending);
+        sending.await.unwrap();
+
+        // Loopback delivers right away, so all are available to the first call
+        let mut buffers = [[0u8; 4]; 4];
+        let mut buffers: Vec<&mut [u8]> = buffers.iter_mut().map(|b| &mut b[..]).collect();
+        let receiving = servsock.receive_batch(&mut buffers);
+        assert_send(&receiving);
+        let received = receiving.await.unwrap();
+        assert_eq!(received.len(), 3);
+        for (i, (length, local, remote)) in received.iter().enumerate() {
+            assert_eq!(*local, addr);
+            assert_eq!(*remote, cli_local);
+            let expected: &[u8] = [&b"one"[..], b"two", b"three"][i];
+            // Truncated, but the full length is reported
+            assert_eq!(*length, expected.len());
+            let stored = expected.len().min(4);
+            assert_eq!(&buffers[i][..stored], &expected[..stored]);
+        }
+
+        let replies: Vec<_> = received
+            .iter()
+            .zip([&b"1"[..], b"2", b"3"])
+            .map(|((_, local, remote), data)| (*local, *remote, data))
+            .collect();
+        servsock.send_batch(&replies).await.unwrap();
+
+        let mut buffers = [[0u8; 4]; 4];
+        let mut buffers: Vec<&mut [u8]> = buffers.iter_mut().map(|b| &mut b[..]).collect();
+        let received = clisock.receive_batch(&mut buffers).await.unwrap();
+        assert_eq!(received, [1, 1, 1]);
+        assert_eq!(&buffers[2][..1], b"3");
+
+        // Invalid local addresses are rejected before anything is sent
+        let wrong_port = "[::1]:4242".parse().unwrap();
+        let err = servsock
+            .send_batch(&[(addr, cli_local, b"4"), (wrong_port, cli_local, b"5")])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    });
+}
+
+#[test]
+fn segmentation_offload() {
+    async_std::task::block_on(async {
+        let stack = std_embedded_nal_async::Stack::default();
+        let (servaddr, mut servsock) = std_embedded_nal_async::Stack::builder()
+            .socket_options(std_embedded_nal_async::SocketOptions::default().receive_offload(true))
+            .build()
+            .bind_single("[::1]:4243".parse().unwrap())
+            .await
+            .unwrap();
+        let mut clisock = stack
+            .bind_multiple("[::]:4244".parse().unwrap())
+            .await
+            .unwrap();
+        let cli_local: SocketAddr = "[::1]:4244".parse().unwrap();
+
+        let data: Vec<u8> = (0..1050).map(|i| i as u8).collect();
+        clisock
+            .send_segmented(cli_local, servaddr, &data, 100.try_into().unwrap())
+            .await
+            .unwrap();
+
+        // Whether or not the kernel coalesces them, all datagrams arrive individually
+        let mut segments = vec![];
+        let mut buffer = vec![0u8; 65536];
+        while segments.len() < 11 {
+            let (local, remote, received) = servsock.receive_coalesced(&mut buffer).await.unwrap();
+            assert_eq!(local, servaddr);
+            assert_eq!(remote, cli_local);
+            segments.extend(received.map(|s| s.to_vec()));
+        }
+        let expected: Vec<_> = data.chunks(100).map(|s| s.to_vec()).collect();
+        assert_eq!(segments, expected);
+
+        // Without receive offload enabled, no receive operation gets coalesced datagrams
+        servsock
+            .send_segmented(servaddr, cli_local, &data[..200], 100.try_into().unwrap())
+            .await
+            .unwrap();
+        let (_, _, received) = clisock.receive_coalesced(&mut buffer).await.unwrap();
+        assert_eq!(received.collect::<Vec<_>>(), [&data[..100]]);
+        let (length, _, _) = clisock.receive_into(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], &data[100..200]);
+    });
+}
+
+/// Check that an oversized datagram towards `remote` is rejected with the path MTU once `reject`
+/// configured the socket to do so.
+///
+/// This relies on a route towards the documentation address `remote` (usually the default route)
+/// whose MTU is below the maximum datagram size, and passes without checking anything otherwise.
+/// Nothing is sent, as the datagram is rejected locally.
+async fn message_too_large(
+    remote: &str,
+    reject: impl FnOnce(&std_embedded_nal_async::ConnectedSocket),
+) {
+    use std_embedded_nal_async::MessageTooLarge;
+
+    let stack = std_embedded_nal_async::Stack::default();
+    let Ok((_, mut sock)) = stack.connect(remote.parse().unwrap()).await else {
+        eprintln!("No route to {remote}, skipping");
+        return;
+    };
+    let mtu = sock.path_mtu().unwrap();
+    if mtu >= 65535 {
+        eprintln!("Path MTU towards {remote} is too large to exceed, skipping");
+        return;
+    }
+
+    reject(&sock);
+    let err = sock.send(&vec![0; mtu + 1]).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    let too_large = MessageTooLarge::from_error(&err).unwrap();
+    assert_eq!(too_large.path_mtu, Some(mtu));
+
+    // Other errors are not mistaken for it
+    let other = std::io::Error::from_raw_os_error(nix::libc::EMSGSIZE);
+    assert!(MessageTooLarge::from_error(&other).is_none());
+}
+
+#[test]
+fn path_mtu() {
+    use std_embedded_nal_async::PathMtuDiscovery;
+
+    async_std::task::block_on(async {
+        message_too_large("192.0.2.1:9", |sock| {
+            sock.set_path_mtu_discovery(PathMtuDiscovery::Do).unwrap()
+        })
+        .await;
+        message_too_large("[2001:db8::1]:9", |sock| {
+            sock.set_dont_fragment(true).unwrap()
+        })
+        .await;
+
+        // The IPv6 option is not available on IPv4 sockets
+        let stack = std_embedded_nal_async::Stack::default();
+        let (_, sock) = stack
+            .bind_single("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        sock.set_path_mtu_discovery(PathMtuDiscovery::Probe)
+            .unwrap();
+        sock.set_dont_fragment(true).unwrap_err();
+    });
+}
+
+/// Send to a port nobody listens on, and check the resulting "port unreachable" error.
+async fn icmp_errors(bind: &str, closed: &str, unreachable: (u8, u8)) {
+    let stack = std_embedded_nal_async::Stack::default();
+    let closed: SocketAddr = closed.parse().unwrap();
+
+    let (local, mut sock) = stack.bind_single(bind.parse().unwrap()).await.unwrap();
+    sock.enable_icmp_errors().unwrap();
+    sock.send(local, closed, b"ping").await.unwrap();
+
+    // The unconnected socket now reports the error at the next receive
+    let mut buffer = [0u8; 4];
+    let err = sock.receive_into(&mut buffer).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+
+    let error = sock.receive_icmp_error().await.unwrap();
+    assert_eq!(error.destination, closed);
+    assert_eq!((error.icmp_type, error.icmp_code), unreachable);
+    assert_eq!(error.reporter, Some(closed.ip()));
+
+    let (_, mut sock) = stack.connect(closed).await.unwrap();
+    sock.enable_icmp_errors().unwrap();
+    sock.send(b"ping").await.unwrap();
+    let error = sock.receive_icmp_error().await.unwrap();
+    assert_eq!(error.destination, closed);
+    assert_eq!((error.icmp_type, error.icmp_code), unreachable);
+}
+
+#[test]
+fn icmp_errorsv4() {
+    async_std::task::block_on(icmp_errors("127.0.0.1:4247", "127.0.0.1:4248", (3, 3)));
+}
+
+#[test]
+fn icmp_errorsv6() {
+    async_std::task::block_on(icmp_errors("[::1]:4247", "[::1]:4248", (1, 4)));
+}
+
+/// CPU time the process used so far
+///
+/// This is the whole process rather than the current thread, as waiting may also keep the reactor
+/// thread busy; the other tests are short enough not to matter.
+fn cpu_time() -> std::time::Duration {
+    use nix::libc;
+    // SAFETY: rusage is plain old data, for which all zeros is a valid value, and getrusage only
+    // writes to it.
+    let mut usage: libc::rusage = unsafe { core::mem::zeroed() };
+    assert_eq!(unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) }, 0);
+    let time =
+        |t: libc::timeval| std::time::Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    time(usage.ru_utime) + time(usage.ru_stime)
+}
+
+#[test]
+fn icmp_error_wait() {
+    async_std::task::block_on(async {
+        let stack = std_embedded_nal_async::Stack::default();
+        let (local, mut sock) = stack
+            .bind_single("127.0.0.1:4256".parse().unwrap())
+            .await
+            .unwrap();
+        sock.enable_icmp_errors().unwrap();
+        let (_, mut clisock) = stack.connect(local).await.unwrap();
+        clisock.send(b"ping").await.unwrap();
+
+        // Queued data does not wake the wait for errors
+        let before = cpu_time();
+        let waited = async_std::future::timeout(
+            std::time::Duration::from_millis(200),
+            sock.receive_icmp_error(),
+        )
+        .await;
+        assert!(waited.is_err());
+        assert!(cpu_time() - before < std::time::Duration::from_millis(100));
+
+        let mut buffer = [0u8; 4];
+        let (length, _, _) = sock.receive_into(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], b"ping");
+
+        let closed: SocketAddr = "127.0.0.1:4257".parse().unwrap();
+        sock.send(local, closed, b"ping").await.unwrap();
+        let error = sock.receive_icmp_error().await.unwrap();
+        assert_eq!(error.destination, closed);
+    });
+}
+
+#[test]
+fn icmp_errors_mapped() {
+    async_std::task::block_on(async {
+        // Without V4-mapped mode, V4-mapped peers are reported just as they were given
+        let closed: SocketAddr = "[::ffff:127.0.0.1]:4248".parse().unwrap();
+        let stack = std_embedded_nal_async::Stack::default();
+        let (_, mut sock) = stack.connect(closed).await.unwrap();
+        sock.enable_icmp_errors().unwrap();
+        sock.send(b"ping").await.unwrap();
+        let error = sock.receive_icmp_error().await.unwrap();
+        assert_eq!(error.destination, closed);
+        assert_eq!(error.reporter, Some(closed.ip()));
+
+        // In V4-mapped mode, they are IPv4 addresses at the API
+        let closed: SocketAddr = "127.0.0.1:4248".parse().unwrap();
+        let stack = std_embedded_nal_async::Stack::builder()
+            .v4_mapped(true)
+            .build();
+        let (_, mut sock) = stack.connect(closed).await.unwrap();
+        sock.enable_icmp_errors().unwrap();
+        sock.send(b"ping").await.unwrap();
+        let error = sock.receive_icmp_error().await.unwrap();
+        assert_eq!(error.destination, closed);
+        assert_eq!(error.reporter, Some(closed.ip()));
+    });
+}
+
+#[test]
+fn socket_options() {
+    use std_embedded_nal_async::SocketOptions;
+
+    async_std::task::block_on(async {
+        let shared = std_embedded_nal_async::Stack::builder()
+            .socket_options(SocketOptions::default().reuse_port(true))
+            .build();
+        let port: SocketAddr = "127.0.0.1:4249".parse().unwrap();
+        let _first = shared.bind_single(port).await.unwrap();
+        let _second = shared.bind_single(port).await.unwrap();
+        let err = std_embedded_nal_async::Stack::default()
+            .bind_single(port)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+
+        let marked = std_embedded_nal_async::Stack::builder()
+            .socket_options(
+                SocketOptions::default()
+                    .hop_limit(7)
+                    .dscp(0x2e)
+                    .receive_buffer_size(1 << 16)
+                    .send_buffer_size(1 << 16),
+            )
+            .build();
+        for server in ["127.0.0.1:4250", "[::1]:4250"] {
+            let (servaddr, mut servsock) = std_embedded_nal_async::Stack::builder()
+                .socket_options(SocketOptions::default().receive_metadata(true))
+                .build()
+                .bind_single(server.parse().unwrap())
+                .await
+                .unwrap();
+            let (_, mut clisock) = marked.connect(servaddr).await.unwrap();
+            let mut buffer = [0u8; 4];
+            clisock.send(b"ping").await.unwrap();
+            let (_, _, _, metadata) = servsock
+                .receive_into_with_metadata(&mut buffer)
+                .await
+                .unwrap();
+            assert_eq!(metadata.hop_limit, Some(7));
+            assert_eq!(metadata.traffic_class, Some(0x2e << 2));
+        }
+    });
+}
@@ -0,0 +1,161 @@
+#![cfg(feature = "io-uring")]
+
+use embedded_nal_async::{ConnectedUdp, SocketAddr, UdpStack, UnconnectedUdp};
+use std_embedded_nal_async::uring;
+
+async fn udp_echo(stack: &mut impl UdpStack, addr: &str) {
+    let addr: SocketAddr = addr.parse().unwrap();
+
+    let mut servsock = stack.bind_multiple(addr).await.unwrap();
+    let (cli_local, mut clisock) = stack.connect(addr).await.unwrap();
+
+    clisock.send(b"ping").await.unwrap();
+    let mut buffer = [0u8; 10];
+    let (received, servaddr, server_cliaddr) = servsock.receive_into(&mut buffer).await.unwrap();
+    assert_eq!(received, 4);
+    assert_eq!(&buffer[..4], b"ping");
+    assert_eq!(server_cliaddr, cli_local);
+
+    servsock
+        .send(servaddr, server_cliaddr, b"pong")
+        .await
+        .unwrap();
+    // Too short a buffer still reports the full length
+    let mut buffer = [0u8; 2];
+    let received = clisock.receive_into(&mut buffer).await.unwrap();
+    assert_eq!(received, 4);
+    assert_eq!(&buffer, b"po");
+}
+
+#[test]
+fn udp_echov4() {
+    let mut stack = uring::Stack::default();
+    uring::block_on(udp_echo(&mut stack, "127.0.0.1:4245")).unwrap();
+}
+
+#[test]
+fn udp_echov6() {
+    let mut stack = uring::Stack::default();
+    uring::block_on(udp_echo(&mut stack, "[::1]:4245")).unwrap();
+}
+
+#[test]
+fn udp_echo_v4_mapped() {
+    let mut stack: uring::Stack = std_embedded_nal_async::Stack::builder()
+        .v4_mapped(true)
+        .build()
+        .into();
+    uring::block_on(udp_echo(&mut stack, "127.0.0.1:4246")).unwrap();
+}
+
+#[test]
+fn tcp_echo() {
+    use embedded_io_async::{Read, Write};
+    use embedded_nal_async::TcpConnect;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:4245").unwrap();
+    // Waiting for the server through the standard library's executor wakes the ring from another
+    // thread
+    let server = async_std::task::spawn_blocking(move || {
+        use std::io::{Read, Write};
+
+        let mut servsock = listener.incoming().next().unwrap().unwrap();
+        let mut buffer = [0u8; 4];
+        servsock.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"ping");
+        servsock.write_all(b"pong").unwrap();
+    });
+
+    uring::block_on(async {
+        let stack = uring::Stack::default();
+        let mut clisock = TcpConnect::connect(&stack, "127.0.0.1:4245".parse().unwrap())
+            .await
+            .unwrap();
+
+        clisock.write_all(b"ping").await.unwrap();
+        let mut buffer = [0u8; 4];
+        clisock.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"pong");
+
+        server.await;
+    })
+    .unwrap();
+}
+
+#[test]
+fn outside_block_on() {
+    let stack = uring::Stack::default();
+    let (_, mut sock) =
+        async_std::task::block_on(stack.bind_single("127.0.0.1:0".parse().unwrap())).unwrap();
+    let mut buffer = [0u8; 4];
+    async_std::task::block_on(sock.receive_into(&mut buffer)).unwrap_err();
+
+    // Nested use is rejected as well
+    uring::block_on(async { uring::block_on(async {}).unwrap_err() }).unwrap();
+}
+
+#[test]
+fn cancelled_receive() {
+    use std::future::Future;
+
+    uring::block_on(async {
+        let stack = uring::Stack::default();
+        let (addr, mut sock) = stack
+            .bind_single("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+
+        // Start a receive that can not complete, and drop it after the first poll
+        let mut buffer = [0u8; 4];
+        {
+            let mut receive = std::pin::pin!(sock.receive_into(&mut buffer));
+            core::future::poll_fn(|cx| {
+                assert!(receive.as_mut().poll(cx).is_pending());
+                core::task::Poll::Ready(())
+            })
+            .await;
+        }
+
+        // The socket is usable afterwards, and the datagram goes to the new receive
+        let (_, mut client) = stack.connect(addr).await.unwrap();
+        client.send(b"ping").await.unwrap();
+        let (len, _, _) = sock.receive_into(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..len], b"ping");
+    })
+    .unwrap();
+}
+
+#[test]
+fn reused_buffers() {
+    uring::block_on(async {
+        let stack = uring::Stack::default();
+        let (addr, mut server) = stack
+            .bind_single("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let (_, mut client) = stack.connect(addr).await.unwrap();
+
+        // Receive buffers are reused across differently sized operations, and only ever report
+        // what was received
+        let mut buffer = [0u8; 64];
+        for (sent, room) in [(&[1u8; 48][..], 64), (b"ab", 64), (&[2u8; 32][..], 8)] {
+            client.send(sent).await.unwrap();
+            buffer.fill(0xff);
+            let (len, local, remote) = server.receive_into(&mut buffer[..room]).await.unwrap();
+            assert_eq!(
+                len,
+                sent.len(),
+                "Full length is reported even when truncated"
+            );
+            let copied = len.min(room);
+            assert_eq!(&buffer[..copied], &sent[..copied]);
+            assert!(buffer[copied..].iter().all(|b| *b == 0xff));
+
+            server.send(local, remote, &buffer[..copied]).await.unwrap();
+            let len = client.receive_into(&mut buffer[..4]).await.unwrap();
+            assert_eq!(len, copied);
+            assert_eq!(&buffer[..len.min(4)], &sent[..len.min(4)]);
+        }
+    })
+    .unwrap();
+}
@@ -0,0 +1,63 @@
+//! Tests for socket activation
+//!
+//! This fakes a service manager by placing sockets at the file descriptors the protocol
+//! prescribes. As that affects the whole process, this must be the only test in this file.
+
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+use embedded_nal::nb::block;
+use mm_std_embedded_nal::activation;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+
+#[test]
+fn activated_sockets() {
+    use embedded_nal::{TcpFullStack, UdpClientStack, UdpFullStack};
+
+    let udp = std::net::UdpSocket::bind("[::1]:0").unwrap();
+    let udp_addr = udp.local_addr().unwrap();
+    let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let tcp_addr = tcp.local_addr().unwrap();
+
+    // Moving them out of the way first, as the sockets may have been created at the target
+    // file descriptors
+    let dup_high = |fd| nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(10)).unwrap();
+    let (udp_high, tcp_high) = (dup_high(udp.as_raw_fd()), dup_high(tcp.as_raw_fd()));
+    drop((udp, tcp));
+    nix::unistd::dup2(udp_high, 3).unwrap();
+    nix::unistd::dup2(tcp_high, 4).unwrap();
+    nix::unistd::close(udp_high).unwrap();
+    nix::unistd::close(tcp_high).unwrap();
+
+    std::env::set_var("LISTEN_PID", std::process::id().to_string());
+    std::env::set_var("LISTEN_FDS", "2");
+
+    let mut fds = activation::listen_fds().unwrap().into_iter();
+    assert!(std::env::var("LISTEN_FDS").is_err());
+    assert!(activation::listen_fds().unwrap().is_empty());
+
+    let udp_fd = fds.next().unwrap();
+    let tcp_fd = fds.next().unwrap();
+    assert!(fds.next().is_none());
+
+    assert!(activation::tcp_listener(udp_fd.try_clone().unwrap()).is_err());
+    assert!(activation::udp_socket(tcp_fd.try_clone().unwrap()).is_err());
+    let not_a_socket = OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+    assert!(activation::udp_socket(not_a_socket).is_err());
+
+    let mut stack = mm_std_embedded_nal::Stack::default();
+
+    let mut udp = activation::udp_socket(udp_fd).unwrap();
+    let client = std::net::UdpSocket::bind("[::1]:0").unwrap();
+    client.send_to(b"ping", udp_addr).unwrap();
+    let mut buf = [0; 4];
+    let (_, remote) = block!(stack.receive(&mut udp, &mut buf)).unwrap();
+    assert_eq!(&buf, b"ping");
+    block!(stack.send_to(&mut udp, remote, b"pong")).unwrap();
+    client.recv(&mut buf).unwrap();
+    assert_eq!(&buf, b"pong");
+
+    let mut tcp = activation::tcp_listener(tcp_fd).unwrap();
+    let _client = std::net::TcpStream::connect(tcp_addr).unwrap();
+    let (conn, _) = block!(stack.accept(&mut tcp)).unwrap();
+    assert!(conn.as_raw_fd().is_some());
+}
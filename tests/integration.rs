@@ -7,7 +7,7 @@ const PORT: u16 = 9876;
 fn udp_pingpong_self() {
     use embedded_nal::{UdpClientStack, UdpFullStack};
 
-    let mut stack = std_embedded_nal::Stack::default();
+    let mut stack = mm_std_embedded_nal::Stack::default();
 
     let mut server = stack.socket().unwrap();
 
@@ -35,7 +35,7 @@ fn udp_pingpong_self() {
 fn tcp_pingpong_self() {
     use embedded_nal::{TcpClientStack, TcpFullStack};
 
-    let mut stack = std_embedded_nal::Stack::default();
+    let mut stack = mm_std_embedded_nal::Stack::default();
 
     let mut server = stack.socket().unwrap();
 
@@ -61,3 +61,254 @@ fn tcp_pingpong_self() {
     assert_eq!(res, 4);
     assert_eq!(&buf, b"pong");
 }
+
+#[test]
+fn tcp_std_interop() {
+    use embedded_nal::{TcpClientStack, TcpFullStack};
+    use mm_std_embedded_nal::TcpSocket;
+
+    let mut stack = mm_std_embedded_nal::Stack::default();
+
+    let listener = std::net::TcpListener::bind("[::1]:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut listener = TcpSocket::try_from(listener).unwrap();
+    assert!(listener.as_fd().is_some());
+
+    let mut client = TcpSocket::try_from(std::net::TcpStream::connect(addr).unwrap()).unwrap();
+    let (server, _) = block!(stack.accept(&mut listener)).unwrap();
+
+    block!(stack.send(&mut client, b"ping")).unwrap();
+    let mut server = server.try_into_std_stream().ok().unwrap();
+    server.set_nonblocking(false).unwrap();
+    let mut buf = [0u8; 4];
+    std::io::Read::read_exact(&mut server, &mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    let listener = listener.try_into_std_stream().err().unwrap();
+    assert!(listener.try_into_std_listener().is_ok());
+
+    let unbound = stack.socket().unwrap();
+    assert!(unbound.as_fd().is_none());
+    assert!(unbound.try_into_std_stream().is_err());
+}
+
+#[test]
+fn udp_std_interop() {
+    use embedded_nal::{UdpClientStack, UdpFullStack};
+    use mm_std_embedded_nal::UdpSocket;
+
+    let mut stack = mm_std_embedded_nal::Stack::default();
+    let mut buf = [0u8; 4];
+
+    let server = std::net::UdpSocket::bind("[::1]:0").unwrap();
+    let client = std::net::UdpSocket::bind("[::1]:0").unwrap();
+    client.connect(server.local_addr().unwrap()).unwrap();
+    let mut server = UdpSocket::try_from(server).unwrap();
+    let mut client = UdpSocket::try_from(client).unwrap();
+
+    block!(stack.send(&mut client, b"ping")).unwrap();
+    let (_, remote) = block!(stack.receive(&mut server, &mut buf)).unwrap();
+    assert_eq!(&buf, b"ping");
+    block!(stack.send_to(&mut server, remote, b"pong")).unwrap();
+    block!(stack.receive(&mut client, &mut buf)).unwrap();
+    assert_eq!(&buf, b"pong");
+
+    let client = client.try_into_std().ok().unwrap();
+    assert!(client.peer_addr().is_ok());
+
+    let unbound = stack.socket().unwrap();
+    assert!(unbound.try_into_std().is_err());
+}
+
+#[test]
+fn configured_stack() {
+    use embedded_nal::{Dns, UdpClientStack, UdpFullStack};
+    use mm_std_embedded_nal::FamilyPolicy;
+
+    let mut stack = mm_std_embedded_nal::Stack::builder()
+        .family_policy(FamilyPolicy::Ipv4Only)
+        .bind_address("127.0.0.1".parse().unwrap())
+        .reuse_address(true)
+        .ttl(7)
+        .numeric_hosts_only(true)
+        .build();
+
+    let mut server = stack.socket().unwrap();
+    stack.bind(&mut server, 9877).unwrap();
+    let mut second_server = stack.socket().unwrap();
+    stack.bind(&mut second_server, 9877).unwrap();
+
+    let mut client = stack.socket().unwrap();
+    assert!(stack
+        .connect(&mut client, SocketAddr::new("::1".parse().unwrap(), 9877))
+        .is_err());
+    stack
+        .connect(
+            &mut client,
+            SocketAddr::new("127.0.0.1".parse().unwrap(), 9877),
+        )
+        .unwrap();
+    let client = client.try_into_std().ok().unwrap();
+    assert_eq!(client.ttl().unwrap(), 7);
+    assert_eq!(
+        client.local_addr().unwrap().ip(),
+        "127.0.0.1".parse::<std::net::IpAddr>().unwrap()
+    );
+    let server = server.try_into_std().ok().unwrap();
+    assert_eq!(
+        server.local_addr().unwrap(),
+        "127.0.0.1:9877".parse().unwrap()
+    );
+
+    assert_eq!(
+        block!(stack.get_host_by_name("192.0.2.1", embedded_nal::AddrType::Either)).unwrap(),
+        "192.0.2.1".parse::<std::net::IpAddr>().unwrap()
+    );
+    assert!(block!(stack.get_host_by_name("2001:db8::1", embedded_nal::AddrType::Either)).is_err());
+    assert!(block!(stack.get_host_by_name("localhost", embedded_nal::AddrType::Either)).is_err());
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn socket_hook() {
+    use embedded_nal::{TcpClientStack, TcpFullStack, UdpClientStack, UdpFullStack};
+    use mm_std_embedded_nal::SocketRole;
+    use std::sync::{Arc, Mutex};
+
+    let seen = Arc::new(Mutex::new(vec![]));
+    let hook_seen = seen.clone();
+    let mut stack = mm_std_embedded_nal::Stack::builder()
+        .socket_hook(move |fd, role| {
+            // The socket is not bound yet
+            let socket = std::net::UdpSocket::from(fd.try_clone_to_owned()?);
+            assert_eq!(socket.local_addr()?.port(), 0);
+            hook_seen.lock().unwrap().push(role);
+            Ok(())
+        })
+        .build();
+
+    let mut server = UdpClientStack::socket(&mut stack).unwrap();
+    UdpFullStack::bind(&mut stack, &mut server, 9878).unwrap();
+    let mut client = UdpClientStack::socket(&mut stack).unwrap();
+    UdpClientStack::connect(
+        &mut stack,
+        &mut client,
+        SocketAddr::new("::1".parse().unwrap(), 9878),
+    )
+    .unwrap();
+
+    let mut listener = TcpClientStack::socket(&mut stack).unwrap();
+    TcpFullStack::bind(&mut stack, &mut listener, 9878).unwrap();
+    stack.listen(&mut listener).unwrap();
+    let mut connection = TcpClientStack::socket(&mut stack).unwrap();
+    block!(TcpClientStack::connect(
+        &mut stack,
+        &mut connection,
+        SocketAddr::new("::1".parse().unwrap(), 9878)
+    ))
+    .unwrap();
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            SocketRole::UdpBound,
+            SocketRole::UdpConnected,
+            SocketRole::TcpListener,
+            SocketRole::TcpConnection
+        ]
+    );
+
+    // Errors from the hook fail the operation
+    let mut failing = mm_std_embedded_nal::Stack::builder()
+        .socket_hook(|_, _| Err(std::io::ErrorKind::PermissionDenied.into()))
+        .build();
+    let mut socket = UdpClientStack::socket(&mut failing).unwrap();
+    let err = UdpFullStack::bind(&mut failing, &mut socket, 9879).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn bind_device() {
+    use embedded_nal::{TcpClientStack, TcpFullStack, UdpClientStack, UdpFullStack};
+    use nix::sys::socket::{getsockopt, sockopt};
+
+    let mut stack = mm_std_embedded_nal::Stack::builder()
+        .bind_device("lo")
+        .build();
+
+    let mut server = UdpClientStack::socket(&mut stack).unwrap();
+    UdpFullStack::bind(&mut stack, &mut server, 9870).unwrap();
+    let mut client = UdpClientStack::socket(&mut stack).unwrap();
+    UdpClientStack::connect(
+        &mut stack,
+        &mut client,
+        SocketAddr::new("::1".parse().unwrap(), 9870),
+    )
+    .unwrap();
+    UdpClientStack::send(&mut stack, &mut client, b"ping").unwrap();
+    let mut buffer = [0; 4];
+    let (len, _) = block!(UdpClientStack::receive(
+        &mut stack,
+        &mut server,
+        &mut buffer
+    ))
+    .unwrap();
+    assert_eq!(&buffer[..len], b"ping");
+
+    let mut listener = TcpClientStack::socket(&mut stack).unwrap();
+    TcpFullStack::bind(&mut stack, &mut listener, 9870).unwrap();
+    stack.listen(&mut listener).unwrap();
+    let mut connection = TcpClientStack::socket(&mut stack).unwrap();
+    block!(TcpClientStack::connect(
+        &mut stack,
+        &mut connection,
+        SocketAddr::new("::1".parse().unwrap(), 9870)
+    ))
+    .unwrap();
+
+    let fds = [
+        server.as_fd(),
+        client.as_fd(),
+        listener.as_fd(),
+        connection.as_fd(),
+    ];
+    for fd in fds {
+        let device = getsockopt(&fd.unwrap(), sockopt::BindToDevice).unwrap();
+        // nix reports the name including its terminating NUL
+        assert_eq!(device.to_str().unwrap().trim_end_matches('\0'), "lo");
+    }
+
+    let mut missing = mm_std_embedded_nal::Stack::builder()
+        .bind_device("nonexistent0")
+        .build();
+    let mut socket = UdpClientStack::socket(&mut missing).unwrap();
+    assert!(UdpFullStack::bind(&mut missing, &mut socket, 0).is_err());
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn interfaces() {
+    use mm_std_embedded_nal::FamilyPolicy;
+    use std::net::IpAddr;
+
+    let localhost4: IpAddr = "127.0.0.1".parse().unwrap();
+    let localhost6: IpAddr = "::1".parse().unwrap();
+
+    let interfaces = mm_std_embedded_nal::Stack::default().interfaces().unwrap();
+    let lo = interfaces.iter().find(|i| i.name == "lo").unwrap();
+    assert_eq!(lo.index, nix::net::if_::if_nametoindex("lo").unwrap());
+    assert!(lo.up && lo.loopback);
+    assert!(lo.addresses.contains(&localhost4));
+    assert!(lo.addresses.contains(&localhost6));
+    // Each interface is only listed once, no matter how many addresses it has
+    assert_eq!(interfaces.iter().filter(|i| i.name == "lo").count(), 1);
+
+    let interfaces = mm_std_embedded_nal::Stack::builder()
+        .family_policy(FamilyPolicy::Ipv4Only)
+        .build()
+        .interfaces()
+        .unwrap();
+    let lo = interfaces.iter().find(|i| i.name == "lo").unwrap();
+    assert_eq!(lo.addresses, [localhost4]);
+}
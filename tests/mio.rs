@@ -0,0 +1,98 @@
+#![cfg(feature = "mio")]
+
+use embedded_nal::nb::block;
+use mio::{Events, Interest, Poll, Token};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[test]
+fn udp_registered_before_bind() {
+    use embedded_nal::{UdpClientStack, UdpFullStack};
+
+    let mut stack = mm_std_embedded_nal::Stack::default();
+    let mut poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(4);
+
+    let mut server = stack.socket().unwrap();
+    poll.registry()
+        .register(&mut server, Token(1), Interest::READABLE)
+        .unwrap();
+
+    poll.poll(&mut events, Some(Duration::from_millis(10)))
+        .unwrap();
+    assert!(events.is_empty());
+
+    stack.bind(&mut server, 9874).unwrap();
+
+    let mut client = stack.socket().unwrap();
+    stack
+        .connect(&mut client, SocketAddr::new("::1".parse().unwrap(), 9874))
+        .unwrap();
+    block!(stack.send(&mut client, b"ping")).unwrap();
+
+    poll.poll(&mut events, Some(Duration::from_secs(5)))
+        .unwrap();
+    let event = events.iter().next().expect("Bound socket was not reported");
+    assert_eq!(event.token(), Token(1));
+    assert!(event.is_readable());
+
+    let mut buf = [0; 4];
+    stack.receive(&mut server, &mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    poll.registry().deregister(&mut server).unwrap();
+}
+
+#[test]
+fn tcp_registered_before_connect() {
+    use embedded_nal::{TcpClientStack, TcpFullStack};
+
+    let mut stack = mm_std_embedded_nal::Stack::default();
+    let mut poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(4);
+
+    let mut server = stack.socket().unwrap();
+    let mut client = stack.socket().unwrap();
+    poll.registry()
+        .register(&mut server, Token(1), Interest::READABLE)
+        .unwrap();
+    poll.registry()
+        .register(&mut client, Token(2), Interest::READABLE)
+        .unwrap();
+    // Changing the interests of a socket that has no file descriptor yet needs to stick
+    poll.registry()
+        .reregister(
+            &mut client,
+            Token(2),
+            Interest::READABLE | Interest::WRITABLE,
+        )
+        .unwrap();
+
+    stack.bind(&mut server, 9875).unwrap();
+    stack
+        .connect(&mut client, SocketAddr::new("::1".parse().unwrap(), 9875))
+        .unwrap();
+
+    let mut seen_server = false;
+    let mut seen_client = false;
+    while !(seen_server && seen_client) {
+        poll.poll(&mut events, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert!(!events.is_empty(), "Timeout waiting for events");
+        for event in events.iter() {
+            match event.token() {
+                Token(1) => {
+                    assert!(event.is_readable());
+                    seen_server = true;
+                }
+                Token(2) => {
+                    assert!(event.is_writable());
+                    seen_client = true;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    let (_accepted, _) = block!(stack.accept(&mut server)).unwrap();
+}
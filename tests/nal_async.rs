@@ -0,0 +1,150 @@
+#![cfg(all(
+    feature = "embedded-nal-async",
+    any(target_os = "linux", target_os = "android")
+))]
+
+use async_io::block_on;
+use mm_std_embedded_nal::AsyncStack;
+use std::net::SocketAddr;
+
+#[test]
+fn udp_unconnected() {
+    use embedded_nal_async::{ConnectedUdp, UdpStack, UnconnectedUdp};
+
+    let stack = AsyncStack::default();
+
+    block_on(async {
+        let (server_addr, mut server) = stack.bind_single("[::]:0".parse().unwrap()).await.unwrap();
+        let server_addr = SocketAddr::new("::1".parse().unwrap(), server_addr.port());
+        let (client_addr, mut client) = stack.connect(server_addr).await.unwrap();
+
+        client.send(b"ping").await.unwrap();
+        let mut buf = [0; 2];
+        let (len, local, remote) = server.receive_into(&mut buf).await.unwrap();
+        // Truncated, but the full length is reported
+        assert_eq!(len, 4);
+        assert_eq!(&buf, b"pi");
+        assert_eq!(local, server_addr);
+        assert_eq!(remote, client_addr);
+
+        server.send(local, remote, b"pong").await.unwrap();
+        let mut buf = [0; 10];
+        let len = client.receive_into(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"pong");
+    });
+}
+
+#[test]
+fn tcp_connect() {
+    use embedded_io_async::{Read, Write};
+    use embedded_nal_async::TcpConnect;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stack = AsyncStack::default();
+
+    block_on(async {
+        let mut conn = stack.connect(addr).await.unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        conn.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        std::io::Read::read_exact(&mut server, &mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        std::io::Write::write_all(&mut server, b"pong").unwrap();
+        conn.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    });
+}
+
+#[test]
+fn dns() {
+    use embedded_nal_async::Dns;
+
+    let stack = AsyncStack::default();
+    let addr = block_on(stack.get_host_by_name("127.0.0.1", embedded_nal::AddrType::Either));
+    assert_eq!(
+        addr.unwrap(),
+        "127.0.0.1".parse::<std::net::IpAddr>().unwrap()
+    );
+}
+
+#[test]
+fn udp_local_address() {
+    use embedded_nal_async::{ConnectedUdp, UdpStack, UnconnectedUdp};
+    use mm_std_embedded_nal::{FamilyPolicy, Stack};
+
+    let stack = AsyncStack::default();
+
+    block_on(async {
+        let (server_addr, mut server) = stack
+            .bind_single("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let (client_addr, mut client) = stack.connect(server_addr).await.unwrap();
+        let mut buf = [0; 10];
+
+        // Wrong port, address or family
+        for local in [
+            SocketAddr::new(server_addr.ip(), server_addr.port().wrapping_add(1)),
+            SocketAddr::new("127.0.0.2".parse().unwrap(), server_addr.port()),
+            SocketAddr::new("::1".parse().unwrap(), server_addr.port()),
+            "[::]:0".parse().unwrap(),
+        ] {
+            let err = server.send(local, client_addr, b"x").await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{local}");
+        }
+
+        // Port 0 stands for the bound port, and the unspecified address for the bound address
+        for local in [
+            server_addr,
+            SocketAddr::new(server_addr.ip(), 0),
+            "0.0.0.0:0".parse().unwrap(),
+        ] {
+            server.send(local, client_addr, b"pong").await.unwrap();
+            let len = client.receive_into(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"pong");
+        }
+    });
+
+    // The remote address is checked against the family policy, whether or not packet info is used
+    let stack = AsyncStack::new(
+        Stack::builder()
+            .family_policy(FamilyPolicy::Ipv6Only)
+            .build(),
+    );
+    block_on(async {
+        let (_, mut server) = stack.bind_single("[::]:0".parse().unwrap()).await.unwrap();
+        for local in ["[::]:0", "[::1]:0"] {
+            let remote = "127.0.0.1:9".parse().unwrap();
+            let err = server
+                .send(local.parse().unwrap(), remote, b"x")
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(err.to_string().contains("not allowed"), "{err}");
+        }
+    });
+}
+
+#[test]
+fn udp_connect_from_family() {
+    use embedded_nal_async::UdpStack;
+    use mm_std_embedded_nal::{FamilyPolicy, Stack};
+
+    let stack = AsyncStack::new(
+        Stack::builder()
+            .family_policy(FamilyPolicy::Ipv4Only)
+            .build(),
+    );
+    block_on(async {
+        let err = stack
+            .connect_from("[::]:0".parse().unwrap(), "127.0.0.1:9".parse().unwrap())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("not allowed"), "{err}");
+    });
+}
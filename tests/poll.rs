@@ -0,0 +1,147 @@
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+use embedded_nal::nb::block;
+use mm_std_embedded_nal::poll::{Interest, Poll, Readiness};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[test]
+fn udp_wait_any() {
+    use embedded_nal::{UdpClientStack, UdpFullStack};
+
+    let mut stack = mm_std_embedded_nal::Stack::default();
+    let mut poll = Poll::new().unwrap();
+
+    let mut server_a = stack.socket().unwrap();
+    stack.bind(&mut server_a, 9871).unwrap();
+    let mut server_b = stack.socket().unwrap();
+    stack.bind(&mut server_b, 9872).unwrap();
+
+    let mut client = stack.socket().unwrap();
+    stack
+        .connect(&mut client, SocketAddr::new("::1".parse().unwrap(), 9872))
+        .unwrap();
+
+    let ready = poll
+        .wait(
+            &[
+                ((&server_a).into(), Interest::READABLE),
+                ((&server_b).into(), Interest::READABLE),
+            ],
+            Some(Duration::from_millis(10)),
+        )
+        .unwrap();
+    assert_eq!(ready, []);
+
+    block!(stack.send(&mut client, b"ping")).unwrap();
+
+    let ready = poll
+        .wait(
+            &[
+                ((&server_a).into(), Interest::READABLE),
+                ((&server_b).into(), Interest::READABLE),
+                ((&client).into(), Interest::READABLE),
+            ],
+            None,
+        )
+        .unwrap();
+    assert_eq!(
+        ready,
+        [Readiness {
+            index: 1,
+            readable: true,
+            writable: false
+        }]
+    );
+
+    let mut buf = [0; 4];
+    stack.receive(&mut server_b, &mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[test]
+fn long_timeout() {
+    use embedded_nal::{UdpClientStack, UdpFullStack};
+
+    let mut stack = mm_std_embedded_nal::Stack::default();
+    let mut poll = Poll::new().unwrap();
+
+    let mut socket = stack.socket().unwrap();
+    stack.bind(&mut socket, 9869).unwrap();
+
+    // Timeouts beyond what epoll takes must neither overflow nor wrap around
+    for timeout in [Duration::MAX, Duration::from_secs(50 * 24 * 3600)] {
+        let ready = poll
+            .wait(&[((&socket).into(), Interest::WRITABLE)], Some(timeout))
+            .unwrap();
+        assert_eq!(
+            ready,
+            [Readiness {
+                index: 0,
+                readable: false,
+                writable: true
+            }]
+        );
+    }
+}
+
+#[test]
+fn building_and_tcp() {
+    use embedded_nal::{TcpClientStack, TcpFullStack};
+
+    let mut stack = mm_std_embedded_nal::Stack::default();
+    let mut poll = Poll::new().unwrap();
+
+    let unbound = stack.socket().unwrap();
+    let mut server = stack.socket().unwrap();
+    stack.bind(&mut server, 9873).unwrap();
+
+    let ready = poll
+        .wait(
+            &[
+                ((&unbound).into(), Interest::READABLE | Interest::WRITABLE),
+                ((&server).into(), Interest::READABLE),
+            ],
+            None,
+        )
+        .unwrap();
+    assert_eq!(
+        ready,
+        [Readiness {
+            index: 0,
+            readable: true,
+            writable: true
+        }]
+    );
+
+    let mut client = stack.socket().unwrap();
+    let ready = poll
+        .wait(
+            &[((&client).into(), Interest::WRITABLE)],
+            Some(Duration::ZERO),
+        )
+        .unwrap();
+    assert_eq!(ready.len(), 1, "Unconnected socket is ready");
+
+    stack
+        .connect(&mut client, SocketAddr::new("::1".parse().unwrap(), 9873))
+        .unwrap();
+
+    let ready = poll
+        .wait(&[((&server).into(), Interest::READABLE)], None)
+        .unwrap();
+    assert_eq!(ready.len(), 1);
+    let (_accepted, _) = stack.accept(&mut server).unwrap();
+
+    let ready = poll
+        .wait(&[((&client).into(), Interest::WRITABLE)], None)
+        .unwrap();
+    assert_eq!(
+        ready,
+        [Readiness {
+            index: 0,
+            readable: false,
+            writable: true
+        }]
+    );
+}
@@ -0,0 +1,73 @@
+#![cfg(unix)]
+
+use embedded_nal::nb::block;
+use mm_std_embedded_nal::unix::UnixStack;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// A fresh directory for the sockets of one test
+fn directory(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("std-embedded-nal-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn udp() {
+    use embedded_nal::{UdpClientStack, UdpFullStack};
+
+    let dir = directory("udp");
+    let mut stack = UnixStack::new(&dir);
+
+    let mut server = stack.socket().unwrap();
+    stack.bind(&mut server, 5683).unwrap();
+    assert!(dir.join("5683").exists());
+
+    let mut client = stack.socket().unwrap();
+    stack
+        .connect(&mut client, SocketAddr::new("::1".parse().unwrap(), 5683))
+        .unwrap();
+
+    block!(stack.send(&mut client, b"ping")).unwrap();
+    let mut buf = [0; 4];
+    let (_, remote) = block!(stack.receive(&mut server, &mut buf)).unwrap();
+    assert_eq!(&buf, b"ping");
+    assert_eq!(remote.ip(), "::1".parse::<std::net::IpAddr>().unwrap());
+
+    block!(stack.send_to(&mut server, remote, b"pong")).unwrap();
+    let (_, from) = block!(stack.receive(&mut client, &mut buf)).unwrap();
+    assert_eq!(&buf, b"pong");
+    assert_eq!(from.port(), 5683);
+
+    drop(server);
+    drop(client);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn tcp() {
+    use embedded_nal::{TcpClientStack, TcpFullStack};
+
+    let dir = directory("tcp");
+    let mut stack = UnixStack::new(&dir);
+
+    let mut listener = stack.socket().unwrap();
+    stack.bind(&mut listener, 80).unwrap();
+    stack.listen(&mut listener).unwrap();
+
+    let mut client = stack.socket().unwrap();
+    block!(stack.connect(&mut client, SocketAddr::new("::1".parse().unwrap(), 80))).unwrap();
+    let (mut server, _) = block!(stack.accept(&mut listener)).unwrap();
+
+    block!(stack.send(&mut client, b"ping")).unwrap();
+    let mut buf = [0; 4];
+    let len = block!(stack.receive(&mut server, &mut buf)).unwrap();
+    assert_eq!(&buf[..len], b"ping");
+
+    drop(listener);
+    assert!(!dir.join("80").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}